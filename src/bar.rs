@@ -2,13 +2,17 @@ extern crate gdk;
 extern crate gtk;
 extern crate relm;
 
-use config::Config;
+use config::{Config, WorkspaceBackend};
 use gdk::prelude::*;
 use gtk::Inhibit;
 use gtk::prelude::*;
 use relm::{Component, ContainerWidget, EventStream, Relm, Update, Widget};
 
-use ::widgets::workspace::WorkspaceWidget;
+use ::widgets::workspace::{self, WorkspaceWidget};
+use ::widgets::scratchpad::ScratchpadWidget;
+use ::widgets::window_title::WindowTitleWidget;
+use ::widgets::mode::ModeWidget;
+use ::widgets::music::MusicWidget;
 use ::widgets::monitor_bar::{MonitorBarMsg, MonitorBarWidget};
 use ::manager::{Manager, ManagerMsg};
 use ::monitor::Monitor;
@@ -24,12 +28,17 @@ pub enum BarMsg {
 
 #[allow(unused)] // We must store Components to keep their channels
 pub struct Bar {
-    model:       BarModel,
-    config:      &'static Config,
-    window:      gtk::Window,
-    workspaces:  Component<WorkspaceWidget>,
-    manager:     EventStream<ManagerMsg>,
-    bar_display: Component<MonitorBarWidget>,
+    model:        BarModel,
+    config:       &'static Config,
+    window:       gtk::Window,
+    workspaces:   Component<WorkspaceWidget>,
+    /// Only under i3 or sway, like the mode and the window title.
+    scratchpad:   Option<Component<ScratchpadWidget>>,
+    mode:         Option<Component<ModeWidget>>,
+    window_title: Option<Component<WindowTitleWidget>>,
+    music:        Component<MusicWidget>,
+    manager:      EventStream<ManagerMsg>,
+    bar_display:  Component<MonitorBarWidget>,
 }
 
 impl Bar {
@@ -83,6 +92,10 @@ impl Widget for Bar {
         }
         screen.set_resolution(96.0);
 
+        // Decided once, so the widgets that need i3's IPC aren't started without it
+        config.workspaces.backend = workspace::resolve_backend(config.workspaces.backend);
+        let i3 = config.workspaces.backend == WorkspaceBackend::I3;
+
         let height = config.dpi_scale(25);
 
         let config = Box::new(config);
@@ -121,7 +134,11 @@ impl Widget for Bar {
         container.set_vexpand(true);
         window.add(&container);
 
-        let workspaces = container.add_widget::<WorkspaceWidget>(config);
+        let workspaces   = container.add_widget::<WorkspaceWidget>(config);
+        let scratchpad   = if i3 { Some(container.add_widget::<ScratchpadWidget>(config)) } else { None };
+        let mode         = if i3 { Some(container.add_widget::<ModeWidget>(config)) } else { None };
+        let window_title = if i3 { Some(container.add_widget::<WindowTitleWidget>(config)) } else { None };
+        let music        = container.add_widget::<MusicWidget>(config);
 
         let manager          = relm::execute::<Manager>(config);
         let bar_display      = container.add_widget::<MonitorBarWidget>(config);
@@ -151,6 +168,10 @@ impl Widget for Bar {
             config,
             window,
             workspaces,
+            scratchpad,
+            mode,
+            window_title,
+            music,
             manager,
             bar_display,
         }
@@ -5,18 +5,197 @@ use std::env;
 use std::path::PathBuf;
 
 use color::Color;
+use monitor::Relevance;
+use template::Template;
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub dpi:    f64,
-    pub colors: HashMap<String, Color>,
-    pub mpd:    MpdConfig,
+    pub dpi:          f64,
+    pub colors:       HashMap<String, Color>,
+    #[serde(default)]
+    pub mpd:          MpdConfig,
+    pub music:        MusicConfig,
+    pub workspaces:   WorkspacesConfig,
+    pub window_title: WindowTitleConfig,
+    pub mode:         ModeConfig,
+    pub battery:      BatteryConfig,
+    pub clock:        ClockConfig,
 }
 
-#[derive(Deserialize)]
+/// Unset fields fall back to `MPD_HOST` and `MPD_PORT`, and then to localhost:6600. Hosts starting
+/// with a slash are unix sockets, and can be prefixed with a password like `password@host`.
+#[derive(Default, Deserialize)]
 pub struct MpdConfig {
-    pub host: String,
-    pub port: u16,
+    pub host:     Option<String>,
+    pub port:     Option<u16>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MusicConfig {
+    pub backend:          MusicBackend,
+    pub format:           MusicFormat,
+    pub max_width:        f64,
+    pub overflow:         TextOverflow,
+    /// Show the album art before the song.
+    pub art:              bool,
+    /// MPD's music directory, to look for cover files and lyrics next to songs in.
+    pub directory:        Option<PathBuf>,
+    pub lyrics:           LyricsDisplay,
+    /// Where else to look for LRC files, by `artist - title.lrc` or the song's file name.
+    pub lyrics_directory: Option<PathBuf>,
+}
+
+/// The text to show in each state, with the song's tags, `{elapsed}` and `{duration}` as
+/// placeholders. Nothing is shown for empty ones.
+#[derive(Deserialize)]
+pub struct MusicFormat {
+    pub playing: Template,
+    pub paused:  Template,
+    pub stopped: Template,
+}
+
+/// Where to get the song from. `Mpris` follows whichever player on the session bus was most
+/// recently active.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MusicBackend {
+    Mpd,
+    Mpris,
+}
+
+/// Where to show the line of the lyrics being sung, when there's an LRC file for the song.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsDisplay {
+    None,
+    /// After the song.
+    Bar,
+    /// Along with the lines around it, in the popup.
+    Popup,
+}
+
+/// What to do with text wider than its maximum width.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextOverflow {
+    Ellipsize,
+    /// Scroll back and forth, waiting a moment at each end.
+    Scroll,
+}
+
+#[derive(Deserialize)]
+pub struct WorkspacesConfig {
+    pub backend:          WorkspaceBackend,
+    pub windows:          WindowDisplay,
+    /// Glyphs for window classes, keyed by lowercase class name.
+    pub icons:            HashMap<String, String>,
+    pub default_icon:     String,
+    pub urgent_animation: UrgentAnimation,
+    /// How many workspaces to show on each screen even if they don't exist, by screen number.
+    pub min_desktops:     Vec<i64>,
+    /// Screen numbers from left to right.
+    pub screen_order:     Vec<i64>,
+}
+
+/// How to talk to the window manager. `Auto` uses i3 IPC if an i3 or sway socket can be found,
+/// and EWMH hints otherwise.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceBackend {
+    Auto,
+    I3,
+    Ewmh,
+}
+
+/// How urgent workspaces draw attention to themselves.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UrgentAnimation {
+    None,
+    Pulse,
+    Blink,
+}
+
+/// What to show about the windows on each workspace.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowDisplay {
+    None,
+    Count,
+    Icons,
+}
+
+#[derive(Deserialize)]
+pub struct WindowTitleConfig {
+    pub max_width:     f64,
+    pub show_class:    bool,
+    pub show_instance: bool,
+    pub rewrites:      Vec<TitleRewrite>,
+}
+
+/// Replaces `from` with `to` in the titles of windows whose class matches `class`, or of all
+/// windows if no class is given.
+#[derive(Deserialize)]
+pub struct TitleRewrite {
+    pub class: Option<String>,
+    pub from:  String,
+    #[serde(default)]
+    pub to:    String,
+}
+
+#[derive(Deserialize)]
+pub struct ModeConfig {
+    pub foreground: Color,
+    pub background: Color,
+}
+
+#[derive(Deserialize)]
+pub struct BatteryConfig {
+    /// With `{percentage}`, `{state}`, its `{glyph}` and the `{remaining}` time as placeholders.
+    pub format:   Template,
+    pub backend:  BatteryBackend,
+    /// Seconds between reads when nothing announces a change.
+    pub interval: u64,
+    #[serde(default)]
+    pub thresholds: Vec<BatteryThreshold>,
+    /// Percentage points the batteries have to charge above a threshold before it's crossed again.
+    pub hysteresis: u8,
+}
+
+/// Applies while the batteries are discharging at or below `level` percent. The lowest of those
+/// thresholds picks the color (a name from `colors`) and the relevance, falling back to higher
+/// ones where it doesn't set them, and then to urgent. The command is run with `sh -c` once per
+/// crossing.
+#[derive(Deserialize)]
+pub struct BatteryThreshold {
+    pub level:     u8,
+    pub color:     Option<String>,
+    pub relevance: Option<Relevance>,
+    pub command:   Option<String>,
+}
+
+/// How to hear about the batteries changing. `Auto` uses acpid if it's running, netlink uevents
+/// otherwise, and polls if neither works.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatteryBackend {
+    Auto,
+    Acpid,
+    Uevent,
+    Poll,
+}
+
+#[derive(Deserialize)]
+pub struct ClockConfig {
+    /// Like strftime's, with day and month names in the locale's language.
+    pub format:   String,
+    /// Whether to update every second, for formats showing them, rather than every minute.
+    pub seconds:  bool,
+    /// Like `de_DE.UTF-8`, instead of the one from the environment.
+    pub locale:   Option<String>,
+    /// Like `Europe/Berlin`, instead of the local timezone.
+    pub timezone: Option<String>,
 }
 
 impl Config {
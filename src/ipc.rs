@@ -0,0 +1,458 @@
+//! A small client for the i3 IPC protocol. Sway speaks the same protocol, but adds (and sometimes
+//! omits) fields in its replies, so every reply type here is deserialized leniently.
+
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use serde::de::DeserializeOwned;
+use serde_json;
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+pub const RUN_COMMAND:    u32 = 0;
+pub const GET_WORKSPACES: u32 = 1;
+pub const SUBSCRIBE:      u32 = 2;
+pub const GET_TREE:       u32 = 4;
+
+/// Event replies have the highest bit of their type set.
+const EVENT_MASK: u32 = 1 << 31;
+
+pub const EVENT_WORKSPACE: u32 = EVENT_MASK | 0;
+pub const EVENT_MODE:      u32 = EVENT_MASK | 2;
+pub const EVENT_WINDOW:    u32 = EVENT_MASK | 3;
+pub const EVENT_SHUTDOWN:  u32 = EVENT_MASK | 6;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Workspace {
+    pub num:     i64,
+    pub name:    String,
+    pub visible: bool,
+    pub focused: bool,
+    pub urgent:  bool,
+    pub output:  String,
+}
+
+/// A container in the layout tree: the root, an output, a workspace, a split or a window.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Node {
+    pub id:                i64,
+    pub name:              Option<String>,
+    #[serde(rename = "type")]
+    pub kind:              String,
+    pub layout:            String,
+    pub focused:           bool,
+    pub urgent:            bool,
+    pub marks:             Vec<String>,
+    pub window:            Option<i64>,
+    pub window_properties: Option<WindowProperties>,
+    /// Set by sway for native wayland windows, which have no X11 window properties.
+    pub app_id:            Option<String>,
+    pub nodes:             Vec<Node>,
+    pub floating_nodes:    Vec<Node>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowProperties {
+    pub class:    Option<String>,
+    pub instance: Option<String>,
+    pub title:    Option<String>,
+}
+
+impl Node {
+    pub fn is_window(&self) -> bool {
+        self.window.is_some() || self.app_id.is_some()
+    }
+
+    /// The window class, or the app id for wayland windows under sway.
+    pub fn class(&self) -> Option<&str> {
+        self.window_properties.as_ref().and_then(|p| p.class.as_ref())
+            .or(self.app_id.as_ref())
+            .map(String::as_str)
+    }
+
+    pub fn instance(&self) -> Option<&str> {
+        self.window_properties.as_ref().and_then(|p| p.instance.as_ref()).map(String::as_str)
+    }
+
+    /// The focused node, which isn't necessarily a window.
+    pub fn find_focused(&self) -> Option<&Node> {
+        if self.focused { return Some(self) }
+
+        self.nodes.iter().chain(&self.floating_nodes)
+            .filter_map(Node::find_focused)
+            .next()
+    }
+
+    /// All windows in this container, tiling ones first.
+    pub fn windows(&self) -> Vec<&Node> {
+        if self.is_window() { return vec![ self ] }
+
+        self.nodes.iter().chain(&self.floating_nodes)
+            .flat_map(Node::windows)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CommandOutcome {
+    pub success: bool,
+    pub error:   Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceEvent {
+    pub change:  String,
+    pub current: Option<Node>,
+    pub old:     Option<Node>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowEvent {
+    pub change:    String,
+    pub container: Node,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct ShutdownEvent {
+    /// Either `"restart"` or `"exit"`.
+    change: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ModeEvent {
+    /// Name of the binding mode that was entered, `"default"` when leaving one.
+    pub change:       String,
+    pub pango_markup: bool,
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Workspace(WorkspaceEvent),
+    Window(WindowEvent),
+    Mode(ModeEvent),
+
+    /// An event we subscribed to but don't parse, identified by its type without the event bit.
+    Other(u32),
+}
+
+/// Picks the socket from the values of `SWAYSOCK` and `I3SOCK`. Sway's comes first, because sway
+/// also sets `I3SOCK` for the benefit of i3 tools.
+fn socket_from_env(swaysock: Option<OsString>, i3sock: Option<OsString>) -> Option<PathBuf> {
+    swaysock.or(i3sock).map(PathBuf::from)
+}
+
+/// Finds the IPC socket of the running window manager, from the environment or by asking it.
+pub fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = socket_from_env(env::var_os("SWAYSOCK"), env::var_os("I3SOCK")) {
+        return Some(path);
+    }
+
+    for wm in &[ "i3", "sway" ] {
+        if let Ok(output) = Command::new(wm).arg("--get-socketpath").output() {
+            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !path.is_empty() {
+                return Some(PathBuf::from(path));
+            }
+        }
+    }
+
+    None
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn write_message<W: Write>(w: &mut W, kind: u32, payload: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+    buf.extend_from_slice(MAGIC);
+    buf.write_u32::<NativeEndian>(payload.len() as u32)?;
+    buf.write_u32::<NativeEndian>(kind)?;
+    buf.extend_from_slice(payload);
+    w.write_all(&buf)
+}
+
+fn read_message<R: Read>(r: &mut R) -> io::Result<(u32, Vec<u8>)> {
+    let mut magic = [0; 6];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data("bad magic in ipc message"));
+    }
+
+    let len  = r.read_u32::<NativeEndian>()?;
+    let kind = r.read_u32::<NativeEndian>()?;
+
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload)?;
+
+    Ok((kind, payload))
+}
+
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    /// Connects to whichever window manager `socket_path` finds.
+    pub fn connect() -> io::Result<Connection> {
+        let path = socket_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no i3 or sway socket found"))?;
+
+        Connection::connect_to(path)
+    }
+
+    pub fn connect_to<P: AsRef<Path>>(path: P) -> io::Result<Connection> {
+        Ok(Connection {
+            stream: UnixStream::connect(path)?,
+        })
+    }
+
+    fn request<T: DeserializeOwned>(&mut self, kind: u32, payload: &str) -> io::Result<T> {
+        write_message(&mut self.stream, kind, payload.as_bytes())?;
+
+        let (reply_kind, reply) = read_message(&mut self.stream)?;
+        if reply_kind != kind {
+            return Err(invalid_data(format!("expected reply of type {}, got {}", kind, reply_kind)));
+        }
+
+        serde_json::from_slice(&reply).map_err(invalid_data)
+    }
+
+    pub fn run_command(&mut self, command: &str) -> io::Result<Vec<CommandOutcome>> {
+        self.request(RUN_COMMAND, command)
+    }
+
+    pub fn get_workspaces(&mut self) -> io::Result<Vec<Workspace>> {
+        self.request(GET_WORKSPACES, "")
+    }
+
+    pub fn get_tree(&mut self) -> io::Result<Node> {
+        self.request(GET_TREE, "")
+    }
+
+    /// Turns this connection into one that only receives events. Event names are the ones used
+    /// by i3, e.g. `"workspace"` or `"window"`. The shutdown event is always subscribed to, see
+    /// `EventListener::next_event`.
+    pub fn subscribe(mut self, events: &[&str]) -> io::Result<EventListener> {
+        let mut events = events.to_vec();
+        events.push("shutdown");
+
+        let payload = serde_json::to_string(&events).map_err(invalid_data)?;
+        let reply: CommandOutcome = self.request(SUBSCRIBE, &payload)?;
+
+        if !reply.success {
+            return Err(invalid_data("subscription was refused"));
+        }
+
+        Ok(EventListener {
+            stream: self.stream,
+        })
+    }
+}
+
+pub struct EventListener {
+    stream: UnixStream,
+}
+
+impl EventListener {
+    /// Blocks until the next event arrives. Returns a `ConnectionAborted` error when the window
+    /// manager announces it is restarting or exiting, and an `UnexpectedEof` error when it goes
+    /// away without saying so.
+    pub fn next_event(&mut self) -> io::Result<Event> {
+        let (kind, payload) = read_message(&mut self.stream)?;
+
+        let event = match kind {
+            EVENT_SHUTDOWN  => {
+                let e: ShutdownEvent = serde_json::from_slice(&payload).map_err(invalid_data)?;
+                let msg = format!("window manager is shutting down ({})", e.change);
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, msg));
+            },
+
+            EVENT_WORKSPACE => Event::Workspace(serde_json::from_slice(&payload).map_err(invalid_data)?),
+            EVENT_WINDOW    => Event::Window(serde_json::from_slice(&payload).map_err(invalid_data)?),
+            EVENT_MODE      => Event::Mode(serde_json::from_slice(&payload).map_err(invalid_data)?),
+            _               => Event::Other(kind & !EVENT_MASK),
+        };
+
+        Ok(event)
+    }
+}
+
+pub const MIN_BACKOFF: u64 = 100;
+pub const MAX_BACKOFF: u64 = 5000;
+
+/// Keeps `watch` running across window manager restarts and reloads. `watch` is given the socket
+/// path and should connect, subscribe and query everything it needs from scratch, returning only
+/// once the connection is lost. `disconnected` is then called, and the connection is retried with
+/// an exponential backoff, which is reset once a connection has lasted a while. Returns when there
+/// is no socket to connect to, because neither i3 nor sway is running.
+pub fn supervise<W, D>(mut watch: W, mut disconnected: D)
+where
+    W: FnMut(&Path) -> io::Result<()>,
+    D: FnMut(),
+{
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let path = match socket_path() {
+            Some(path) => path,
+            None       => {
+                println!("no i3 or sway socket found, not watching the window manager");
+                disconnected();
+                return;
+            },
+        };
+
+        let started = Instant::now();
+        let res = watch(&path);
+
+        if started.elapsed() > Duration::from_millis(MAX_BACKOFF) {
+            backoff = MIN_BACKOFF;
+        }
+
+        if let Err(e) = res {
+            println!("lost connection to the window manager: {}, retrying in {}ms", e, backoff);
+        }
+        disconnected();
+
+        thread::sleep(Duration::from_millis(backoff));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// A stand-in for the window manager, so code using the IPC can be tested without one running.
+#[cfg(test)]
+pub mod fake {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    use std::thread;
+
+    use super::*;
+
+    static SERVER_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    pub struct FakeServer {
+        pub path: PathBuf,
+    }
+
+    impl FakeServer {
+        /// Answers every request with the reply registered for its type (or `[]`), and after a
+        /// subscription sends each of `events` before hanging up, like a window manager exiting.
+        pub fn start(replies: Vec<(u32, &str)>, events: Vec<(u32, &str)>) -> FakeServer {
+            let n = SERVER_COUNT.fetch_add(1, Ordering::SeqCst);
+            let path = env::temp_dir().join(format!("obsidian-ipc-{}-{}.sock", process::id(), n));
+            let _ = fs::remove_file(&path);
+
+            let listener = UnixListener::bind(&path).expect("failed to bind fake ipc socket");
+
+            let replies: HashMap<u32, String> = replies.into_iter().map(|(k, r)| (k, r.to_string())).collect();
+            let events: Vec<(u32, String)>    = events.into_iter().map(|(k, e)| (k, e.to_string())).collect();
+
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream { Ok(s) => s, Err(_) => break };
+                    let replies = replies.clone();
+                    let events  = events.clone();
+
+                    thread::spawn(move || {
+                        while let Ok((kind, _payload)) = read_message(&mut stream) {
+                            if kind == SUBSCRIBE {
+                                let _ = write_message(&mut stream, kind, br#"{"success":true}"#);
+                                for (kind, event) in &events {
+                                    let _ = write_message(&mut stream, *kind, event.as_bytes());
+                                }
+                                return;
+                            }
+
+                            let reply = replies.get(&kind).map(String::as_str).unwrap_or("[]");
+                            let _ = write_message(&mut stream, kind, reply.as_bytes());
+                        }
+                    });
+                }
+            });
+
+            FakeServer { path }
+        }
+    }
+
+    impl Drop for FakeServer {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::fake::FakeServer;
+
+    #[test]
+    fn prefers_sway_socket() {
+        let i3   = || Some(OsString::from("/run/user/1000/i3/ipc-socket.1234"));
+        let sway = || Some(OsString::from("/run/user/1000/sway-ipc.1000.1234.sock"));
+
+        assert_eq!(socket_from_env(sway(), i3()), Some(PathBuf::from("/run/user/1000/sway-ipc.1000.1234.sock")));
+        assert_eq!(socket_from_env(None, i3()), Some(PathBuf::from("/run/user/1000/i3/ipc-socket.1234")));
+        assert_eq!(socket_from_env(None, None), None);
+    }
+
+    #[test]
+    fn run_command() {
+        let server = FakeServer::start(vec![ (RUN_COMMAND, r#"[{"success":true}]"#) ], vec![]);
+
+        let mut conn = Connection::connect_to(&server.path).unwrap();
+        let outcome = conn.run_command("workspace 1-1").unwrap();
+
+        assert_eq!(outcome.len(), 1);
+        assert!(outcome[0].success);
+    }
+
+    #[test]
+    fn events_then_eof() {
+        let server = FakeServer::start(vec![], vec![
+            (EVENT_WORKSPACE, r#"{"change":"focus","current":null,"old":null}"#),
+        ]);
+
+        let mut listener = Connection::connect_to(&server.path).unwrap().subscribe(&[ "workspace" ]).unwrap();
+
+        match listener.next_event().unwrap() {
+            Event::Workspace(e) => assert_eq!(e.change, "focus"),
+            e                   => panic!("unexpected event {:?}", e),
+        }
+
+        assert_eq!(listener.next_event().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn shutdown_is_disconnect() {
+        let server = FakeServer::start(vec![], vec![
+            (EVENT_SHUTDOWN,  r#"{"change":"restart"}"#),
+            (EVENT_WORKSPACE, r#"{"change":"focus"}"#),
+        ]);
+
+        let mut listener = Connection::connect_to(&server.path).unwrap().subscribe(&[ "workspace" ]).unwrap();
+
+        assert_eq!(listener.next_event().unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
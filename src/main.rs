@@ -3,25 +3,30 @@
 #[macro_use] extern crate relm;
 #[macro_use] extern crate relm_derive;
 #[macro_use] extern crate serde_derive;
+extern crate byteorder;
 extern crate gdk;
 extern crate gdk_sys;
 extern crate glib;
 extern crate gtk;
-extern crate i3ipc;
 extern crate itertools;
 extern crate relm_core;
 extern crate serde;
+extern crate serde_json;
 extern crate time;
 
 #[macro_use] mod util;
 mod color;
 mod config;
+mod ipc;
 mod manager;
+mod media;
 mod monitor;
 mod bar;
 mod status;
+mod template;
 mod widgets;
 
 fn main() {
+    widgets::workspace::init_threads();
     relm::run::<bar::Bar>(()).unwrap();
 }
@@ -59,11 +59,15 @@ impl Update for Manager {
         let mut monitors = vec![];
         let mut channels = vec![];
 
-        let battery = Battery::default();
-        let (ch, sx) = create_channel(relm, monitors.len());
-        battery.start(config, sx);
-        monitors.push(empty_state());
-        channels.push(ch);
+        match Battery::new() {
+            Ok(battery) => {
+                let (ch, sx) = create_channel(relm, monitors.len());
+                battery.start(config, sx);
+                monitors.push(empty_state());
+                channels.push(ch);
+            },
+            Err(e) => println!("not showing the battery: {}", e),
+        }
 
         let clock = Clock::default();
         let (ch, sx) = create_channel(relm, monitors.len());
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use ::config::{Config, MusicBackend};
+use super::{mpd, Song};
+
+/// Files next to songs that hold their album art, in order of preference.
+const COVERS: &[&str] = &[ "cover.jpg", "cover.png", "folder.jpg", "folder.png" ];
+
+/// What to cache a song's art by, songs in the same directory and album share it. `None` for
+/// songs without a known location.
+pub fn album_key(song: &Song) -> Option<String> {
+    if song.uri.is_empty() { return None }
+
+    let directory = Path::new(&song.uri).parent().map_or(String::new(), |dir| dir.to_string_lossy().into_owned());
+    Some(format!("{}\0{}", directory, song.tags.get("album").map_or("", |album| album.as_str())))
+}
+
+/// Looks for a cover file in the song's directory, which is relative to `music_directory` unless
+/// it's absolute.
+fn find_cover(music_directory: Option<&Path>, uri: &str) -> Option<Vec<u8>> {
+    let directory = Path::new(uri).parent()?;
+    let directory = if directory.is_absolute() {
+        directory.to_path_buf()
+    } else {
+        music_directory?.join(directory)
+    };
+
+    COVERS.iter().filter_map(|name| {
+        let mut data = vec![];
+        File::open(directory.join(name)).and_then(|mut file| file.read_to_end(&mut data)).ok()?;
+        Some(data)
+    }).next()
+}
+
+/// Fetches the encoded album art for the song at `uri`, from MPD if it's the backend and otherwise
+/// from a cover file in the music directory. Fails if MPD couldn't be asked and there's no cover
+/// file either, so it can be tried again later. Blocks, so it's best called on a thread of its own.
+pub fn fetch(config: &Config, uri: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut res = Ok(None);
+
+    if config.music.backend == MusicBackend::Mpd {
+        match mpd::album_art(config, uri) {
+            Ok(Some(data)) => return Ok(Some(data)),
+            Ok(None)       => (),
+            Err(e)         => {
+                println!("failed to fetch album art for {}: {}", uri, e);
+                res = Err(e);
+            },
+        }
+    }
+
+    match find_cover(config.music.directory.as_ref().map(PathBuf::as_path), uri) {
+        Some(data) => Ok(Some(data)),
+        None       => res,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn finds_covers() {
+        let music = env::temp_dir().join(format!("obsidian-music-{}", process::id()));
+        fs::create_dir_all(music.join("Artist/Album")).unwrap();
+        File::create(music.join("Artist/Album/folder.png")).unwrap().write_all(b"png").unwrap();
+
+        assert_eq!(find_cover(Some(&music), "Artist/Album/01 Song.flac"), Some(b"png".to_vec()));
+        assert_eq!(find_cover(None, &music.join("Artist/Album/01 Song.flac").to_string_lossy()), Some(b"png".to_vec()));
+        assert_eq!(find_cover(None, "Artist/Album/01 Song.flac"), None);
+        assert_eq!(find_cover(Some(&music), "Artist/01 Song.flac"), None);
+
+        fs::remove_dir_all(&music).unwrap();
+    }
+}
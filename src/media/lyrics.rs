@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::Song;
+
+/// Lines of synced lyrics with the second they start at, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics(Vec<(f64, String)>);
+
+/// Parses the `mm:ss.xx` of a timestamp tag, also accepting `mm:ss` and `mm:ss:xx`.
+fn parse_time(tag: &str) -> Option<f64> {
+    let mut parts = tag.splitn(2, ':');
+    let minutes = parts.next()?.parse::<u32>().ok()?;
+    let seconds = parts.next()?.replacen(':', ".", 1).parse::<f64>().ok()?;
+
+    // Rust also parses "nan" and "inf", which can't be sorted or waited for
+    if !seconds.is_finite() { return None }
+
+    Some(minutes as f64 * 60.0 + seconds)
+}
+
+impl Lyrics {
+    /// Parses the lines of an LRC file, like `[01:23.45]text`. Lines can have several timestamps,
+    /// and `[offset:ms]` moves all of them earlier. Other tags and word timestamps (`<01:23.45>`)
+    /// are left out.
+    pub fn parse(lrc: &str) -> Lyrics {
+        let mut lines  = vec![];
+        let mut offset = 0.0;
+
+        for line in lrc.lines() {
+            let mut rest  = line.trim();
+            let mut times = vec![];
+
+            while rest.starts_with('[') {
+                let end = match rest.find(']') {
+                    Some(end) => end,
+                    None      => break,
+                };
+
+                let tag = &rest[1..end];
+                if let Some(time) = parse_time(tag) {
+                    times.push(time);
+                } else if tag.starts_with("offset:") {
+                    offset = tag[7..].trim().parse::<f64>().unwrap_or(0.0) / 1000.0;
+                }
+
+                rest = &rest[end + 1..];
+            }
+
+            let mut text = String::new();
+            for (i, part) in rest.split('<').enumerate() {
+                match part.find('>') {
+                    Some(end) if i > 0 && parse_time(&part[..end]).is_some() => text.push_str(&part[end + 1..]),
+                    _ if i > 0 => { text.push('<'); text.push_str(part) },
+                    _          => text.push_str(part),
+                }
+            }
+
+            let text = text.trim();
+            lines.extend(times.into_iter().map(|time| (time, text.to_string())));
+        }
+
+        for line in &mut lines {
+            line.0 = (line.0 - offset).max(0.0);
+        }
+        lines.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        Lyrics(lines)
+    }
+
+    /// The line being sung `position` seconds into the song, if any.
+    pub fn line_at(&self, position: f64) -> Option<&str> {
+        self.0.iter()
+            .take_while(|&&(time, _)| time <= position)
+            .last()
+            .map(|&(_, ref text)| text.as_str())
+            .filter(|text| !text.is_empty())
+    }
+
+    /// The lines around the one being sung, the current one in the middle: `before` lines before
+    /// it and `after` after. Lines outside the song are empty.
+    pub fn lines_around(&self, position: f64, before: usize, after: usize) -> Vec<&str> {
+        let current = self.0.iter().take_while(|&&(time, _)| time <= position).count() as isize - 1;
+
+        (current - before as isize ..= current + after as isize)
+            .map(|i| if i < 0 { "" } else { self.0.get(i as usize).map_or("", |&(_, ref text)| text.as_str()) })
+            .collect()
+    }
+}
+
+/// Looks for an LRC file for the song: next to it, in the lyrics directory as `artist - title.lrc`,
+/// or there with the song's file name. Relative song locations are in the music directory.
+pub fn find(music_directory: Option<&Path>, lyrics_directory: Option<&Path>, song: &Song) -> Option<Lyrics> {
+    if song.uri.is_empty() { return None }
+
+    let song_path = Path::new(&song.uri);
+    let mut candidates: Vec<PathBuf> = vec![];
+
+    if song_path.is_absolute() {
+        candidates.push(song_path.with_extension("lrc"));
+    } else if let Some(music_directory) = music_directory {
+        candidates.push(music_directory.join(song_path).with_extension("lrc"));
+    }
+
+    if let Some(lyrics_directory) = lyrics_directory {
+        if let (Some(artist), Some(title)) = (song.tags.get("artist"), song.tags.get("title")) {
+            // Slashes would make for directories
+            let name = format!("{} - {}.lrc", artist, title).replace('/', "_");
+            candidates.push(lyrics_directory.join(name));
+        }
+        if let Some(stem) = song_path.file_stem() {
+            candidates.push(lyrics_directory.join(stem).with_extension("lrc"));
+        }
+    }
+
+    candidates.iter().filter_map(|path| {
+        let mut data = vec![];
+        File::open(path).and_then(|mut file| file.read_to_end(&mut data)).ok()?;
+        Some(Lyrics::parse(&String::from_utf8_lossy(&data)))
+    }).next()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    use super::super::PlayState;
+    use super::*;
+
+    const LRC: &str = "[ar:Artist]
+[ti:Song]
+[00:01.00]First line
+[00:05.50][01:00.00]Chorus
+[00:03:25]<00:03.25>Second <00:04.00>line
+[00:10.00]
+[00:11]a <b> c
+";
+
+    #[test]
+    fn parses_lrc() {
+        let lyrics = Lyrics::parse(LRC);
+
+        assert_eq!(lyrics, Lyrics(vec![
+            (1.0,  "First line".to_string()),
+            (3.25, "Second line".to_string()),
+            (5.5,  "Chorus".to_string()),
+            (10.0, "".to_string()),
+            (11.0, "a <b> c".to_string()),
+            (60.0, "Chorus".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn malformed_times() {
+        let lyrics = Lyrics::parse("[00:nan]Not a number\n[00:inf]Forever\n[00:01.00]Fine");
+        assert_eq!(lyrics, Lyrics(vec![ (1.0, "Fine".to_string()) ]));
+    }
+
+    #[test]
+    fn offset() {
+        let lyrics = Lyrics::parse("[offset:+500]\n[00:00.20]Early\n[00:02.00]Later");
+        assert_eq!(lyrics, Lyrics(vec![ (0.0, "Early".to_string()), (1.5, "Later".to_string()) ]));
+    }
+
+    #[test]
+    fn lines_at_positions() {
+        let lyrics = Lyrics::parse(LRC);
+
+        let lines = [ 0.0, 1.0, 4.0, 5.6, 10.5, 120.0 ].iter().map(|&t| lyrics.line_at(t)).collect::<Vec<_>>();
+        assert_eq!(lines, vec![ None, Some("First line"), Some("Second line"), Some("Chorus"), None, Some("Chorus") ]);
+
+        assert_eq!(lyrics.lines_around(0.5, 1, 1), vec![ "", "", "First line" ]);
+        assert_eq!(lyrics.lines_around(4.0, 1, 1), vec![ "First line", "Second line", "Chorus" ]);
+        assert_eq!(lyrics.lines_around(61.0, 1, 1), vec![ "a <b> c", "Chorus", "" ]);
+    }
+
+    #[test]
+    fn finds_lrc_files() {
+        let root  = env::temp_dir().join(format!("obsidian-lyrics-{}", process::id()));
+        let music = root.join("music");
+        let lrcs  = root.join("lyrics");
+        fs::create_dir_all(music.join("Artist/Album")).unwrap();
+        fs::create_dir_all(&lrcs).unwrap();
+
+        File::create(music.join("Artist/Album/01 Song.lrc")).unwrap().write_all(b"[00:01.00]next to it").unwrap();
+        File::create(lrcs.join("Artist - AC_DC.lrc")).unwrap().write_all(b"[00:01.00]by tags").unwrap();
+        File::create(lrcs.join("02 Other.lrc")).unwrap().write_all(b"[00:01.00]by file name").unwrap();
+
+        let song = |uri: &str, title: &str| Song {
+            tags:     [ ("artist", "Artist"), ("title", title) ].iter()
+                .map(|&(tag, value)| (tag.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            uri:      uri.to_string(),
+            state:    PlayState::Playing,
+            volume:   -1,
+            elapsed:  0.0,
+            duration: 0.0,
+            synced:   0.0,
+        };
+        let line = |lyrics: Option<Lyrics>| lyrics.and_then(|lyrics| lyrics.line_at(1.0).map(String::from));
+
+        let (music, lrcs) = (Some(music.as_path()), Some(lrcs.as_path()));
+        assert_eq!(line(find(music, lrcs, &song("Artist/Album/01 Song.flac", "Song"))), Some("next to it".to_string()));
+        assert_eq!(line(find(music, lrcs, &song("Artist/Album/03 Third.flac", "AC/DC"))), Some("by tags".to_string()));
+        assert_eq!(line(find(music, lrcs, &song("Artist/Album/02 Other.flac", "Other"))), Some("by file name".to_string()));
+        assert_eq!(line(find(music, None, &song("Artist/Album/02 Other.flac", "Other"))), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+extern crate time;
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use ::config::{Config, MusicBackend};
+
+pub mod art;
+pub mod lyrics;
+pub mod mpd;
+pub mod mpris;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayState {
+    Playing,
+    Paused,
+    /// Stopped, the tags are those of the song that plays next if there is one.
+    Stopped,
+}
+
+/// What a player is playing, and how far along it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Song {
+    /// Lowercase MPD tag names like `artist`, `title` and `albumartist`, with `file` being the
+    /// file name without its directory. Players that don't use MPD's names map theirs to them.
+    pub tags:     HashMap<String, String>,
+    /// Where the song is, relative to MPD's music directory or an absolute path for local files
+    /// MPRIS players play. Empty if unknown.
+    pub uri:      String,
+    pub state:    PlayState,
+    /// In percent, or -1 if the player can't change it.
+    pub volume:   i8,
+    /// Seconds into the song when the status was read.
+    pub elapsed:  f64,
+    /// Length of the song in seconds, 0 for streams.
+    pub duration: f64,
+    /// When the status was read, from `time::precise_time_s`.
+    pub synced:   f64,
+}
+
+impl Song {
+    /// Seconds into the song right now, counting from the last status while playing.
+    pub fn position(&self) -> f64 {
+        if self.state != PlayState::Playing { return self.elapsed }
+
+        let position = self.elapsed + time::precise_time_s() - self.synced;
+        if self.duration > 0.0 { position.min(self.duration) } else { position }
+    }
+}
+
+/// The songs around the current one in MPD's queue, and the saved playlists.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Queue {
+    /// Positions in the queue and what to show for the songs there.
+    pub songs:     Vec<(u32, String)>,
+    pub current:   Option<u32>,
+    pub playlists: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayOption {
+    Random,
+    Repeat,
+    /// Stop after the current song, or repeat it with `Repeat`.
+    Single,
+    /// Remove songs from the queue once they're played.
+    Consume,
+}
+
+/// MPD's playback options and audio outputs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub random:  bool,
+    pub repeat:  bool,
+    pub single:  bool,
+    pub consume: bool,
+    /// Ids, names and whether they're enabled.
+    pub outputs: Vec<(u32, String, bool)>,
+}
+
+impl Options {
+    pub fn get(&self, option: PlayOption) -> bool {
+        match option {
+            PlayOption::Random  => self.random,
+            PlayOption::Repeat  => self.repeat,
+            PlayOption::Single  => self.single,
+            PlayOption::Consume => self.consume,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    TogglePause,
+    Next,
+    Previous,
+    /// Changes the volume by this many percent.
+    Volume(i8),
+    /// Plays the song at this position in the queue.
+    Play(u32),
+    /// Replaces the queue with a saved playlist and plays it.
+    LoadPlaylist(String),
+    SetOption(PlayOption, bool),
+    /// Enables or disables the audio output with this id.
+    SetOutput(u32, bool),
+}
+
+/// Sends commands to a backend's thread, which runs them on its connection to the player.
+#[derive(Debug)]
+pub struct Remote {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Remote {
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    /// A connection to the player was made, commands go through this remote until the next one.
+    Connected(Remote),
+    /// The song or its state changed, or `None` when disconnected.
+    Song(Option<Song>),
+    /// The queue or the saved playlists changed, or the current song moved along the queue. Only
+    /// MPD has these.
+    Queue(Queue),
+    /// The playback options or outputs changed. Also only from MPD.
+    Options(Options),
+}
+
+/// Watches the configured player on a thread of its own, reconnecting when the connection is
+/// lost.
+pub fn spawn<F>(config: &'static Config, emit: F)
+where
+    F: Fn(Event) + Send + 'static,
+{
+    thread::spawn(move || {
+        loop {
+            let res = match config.music.backend {
+                MusicBackend::Mpd   => mpd::watch(config, &emit).map_err(|e| e.to_string()),
+                MusicBackend::Mpris => mpris::watch(None, &emit).map_err(|e| e.to_string()),
+            };
+
+            if let Err(e) = res {
+                println!("lost connection to the player: {}, retrying in 5s", e);
+            }
+
+            emit(Event::Song(None));
+            thread::sleep(Duration::from_secs(5));
+        }
+    });
+}
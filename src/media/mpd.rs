@@ -0,0 +1,568 @@
+extern crate mpd;
+extern crate time;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use self::mpd::Idle;
+
+use ::config::{Config, MpdConfig};
+use super::{Command, Event, Options, PlayOption, PlayState, Queue, Remote, Song};
+
+#[derive(Debug, PartialEq)]
+enum Address {
+    Tcp(String, u16),
+    Unix(PathBuf),
+}
+
+/// Finds where to connect to and the password to use, from the config and then the environment
+/// variables mpc uses.
+fn resolve(config: &MpdConfig, env_host: Option<String>, env_port: Option<String>) -> (Address, Option<String>) {
+    let host = config.host.clone().or(env_host).unwrap_or_else(|| "localhost".to_string());
+
+    let (password, host) = match host.rfind('@') {
+        Some(i) if i > 0 => (Some(host[..i].to_string()), host[i + 1..].to_string()),
+        _                => (None, host),
+    };
+
+    let address = if host.starts_with('/') {
+        Address::Unix(PathBuf::from(host))
+    } else {
+        let port = config.port.or_else(|| env_port.and_then(|port| port.parse().ok())).unwrap_or(6600);
+        Address::Tcp(host, port)
+    };
+
+    (address, config.password.clone().or(password))
+}
+
+/// A connection to MPD over TCP or a unix socket.
+#[derive(Debug)]
+enum MpdStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl MpdStream {
+    fn connect(address: &Address) -> io::Result<MpdStream> {
+        match *address {
+            Address::Tcp(ref host, port) => TcpStream::connect((host.as_str(), port)).map(MpdStream::Tcp),
+            Address::Unix(ref path)       => UnixStream::connect(path).map(MpdStream::Unix),
+        }
+    }
+}
+
+impl Read for MpdStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            MpdStream::Tcp(ref mut stream)  => stream.read(buf),
+            MpdStream::Unix(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for MpdStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            MpdStream::Tcp(ref mut stream)  => stream.write(buf),
+            MpdStream::Unix(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            MpdStream::Tcp(ref mut stream)  => stream.flush(),
+            MpdStream::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// Reads the current song, which has no tags if MPD is stopped with nothing to play next.
+fn current_song(conn: &mut mpd::Client<MpdStream>) -> mpd::error::Result<Song> {
+    let status = conn.status()?;
+    let synced = time::precise_time_s();
+    let state = match status.state {
+        mpd::State::Stop  => PlayState::Stopped,
+        mpd::State::Play  => PlayState::Playing,
+        mpd::State::Pause => PlayState::Paused,
+    };
+
+    let mut tags = HashMap::new();
+    let mut uri  = String::new();
+    if let Some(song) = conn.currentsong()? {
+        tags.extend(song.tags.into_iter().map(|(tag, value)| (tag.to_lowercase(), value)));
+        if let Some(title) = song.title {
+            tags.insert("title".to_string(), title);
+        }
+        tags.insert("file".to_string(), song.file.rsplit('/').next().unwrap_or_default().to_string());
+        uri = song.file;
+    }
+
+    let (elapsed, duration) = status.time
+        .map(|(elapsed, duration)| (elapsed.num_milliseconds() as f64 / 1000.0, duration.num_seconds() as f64))
+        .unwrap_or((0.0, 0.0));
+
+    Ok(Song {
+        tags,
+        uri,
+        state,
+        volume: status.volume,
+        elapsed,
+        duration,
+        synced,
+    })
+}
+
+/// How many songs before the current one to show of the queue, and how many in total.
+const QUEUE_BEFORE: u32 = 5;
+const QUEUE_SHOWN:  u32 = 15;
+
+/// What to show for a song in the queue.
+fn queue_entry(song: &mpd::Song) -> String {
+    match (song.tags.get("Artist"), &song.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title))         => title.clone(),
+        _                           => song.file.rsplit('/').next().unwrap_or_default().to_string(),
+    }
+}
+
+/// Reads the part of the queue around the current song, and the saved playlists.
+fn read_queue(conn: &mut mpd::Client<MpdStream>) -> mpd::error::Result<Queue> {
+    let status  = conn.status()?;
+    let current = status.song.map(|place| place.pos);
+
+    let start = current.unwrap_or(0).saturating_sub(QUEUE_BEFORE);
+    let end   = (start + QUEUE_SHOWN).min(status.queue_len);
+    let songs = if start < end { conn.songs(start..end)? } else { vec![] };
+
+    Ok(Queue {
+        songs: songs.iter()
+            .zip(start..)
+            .map(|(song, pos)| (song.place.map_or(pos, |place| place.pos), queue_entry(song)))
+            .collect(),
+        current,
+        playlists: conn.playlists()?.into_iter().map(|playlist| playlist.name).collect(),
+    })
+}
+
+fn read_options(conn: &mut mpd::Client<MpdStream>) -> mpd::error::Result<Options> {
+    let status = conn.status()?;
+
+    Ok(Options {
+        random:  status.random,
+        repeat:  status.repeat,
+        single:  status.single,
+        consume: status.consume,
+        outputs: conn.outputs()?.into_iter().map(|output| (output.id, output.name, output.enabled)).collect(),
+    })
+}
+
+fn run(conn: &mut mpd::Client<MpdStream>, command: Command) -> mpd::error::Result<()> {
+    match command {
+        Command::TogglePause => conn.toggle_pause(),
+        Command::Next        => conn.next(),
+        Command::Previous    => conn.prev(),
+        Command::Play(pos)   => conn.switch(pos),
+        Command::LoadPlaylist(name) => {
+            conn.clear()?;
+            conn.load(name.as_str(), ..)?;
+            conn.play()
+        },
+        Command::SetOption(PlayOption::Random, on)  => conn.random(on),
+        Command::SetOption(PlayOption::Repeat, on)  => conn.repeat(on),
+        Command::SetOption(PlayOption::Single, on)  => conn.single(on),
+        Command::SetOption(PlayOption::Consume, on) => conn.consume(on),
+        Command::SetOutput(id, on)                  => conn.output(id, on),
+        Command::Volume(delta) => {
+            // -1 means MPD has no mixer to change the volume with
+            let volume = conn.status()?.volume;
+            if volume < 0 { return Ok(()) }
+
+            conn.volume((volume + delta).max(0).min(100))
+        },
+    }
+}
+
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Reads a response line, turning errors from MPD into `io::Error`s.
+fn read_line<S: Read>(stream: &mut BufReader<S>) -> io::Result<String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "MPD closed the connection"));
+    }
+
+    let line = line.trim_end_matches('\n').to_string();
+    if line.starts_with("ACK ") {
+        return Err(io::Error::new(io::ErrorKind::Other, line));
+    }
+
+    Ok(line)
+}
+
+/// Reads a picture in chunks with `albumart` or `readpicture`, or `None` if there isn't one.
+fn read_binary<S: Read + Write>(stream: &mut BufReader<S>, command: &str, uri: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+
+    loop {
+        writeln!(stream.get_mut(), "{} {} {}", command, quote(uri), data.len())?;
+
+        let mut size = None;
+        let chunk = loop {
+            let line = match read_line(stream) {
+                Ok(line) => line,
+                // Also what older versions of MPD, without these commands, say
+                Err(ref e) if e.kind() == io::ErrorKind::Other => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            if line == "OK" { return Ok(None) }
+            if line.starts_with("size: ") {
+                size = line[6..].parse::<usize>().ok();
+            }
+            if line.starts_with("binary: ") {
+                break line[8..].parse::<usize>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        };
+
+        let start = data.len();
+        data.resize(start + chunk, 0);
+        stream.read_exact(&mut data[start..])?;
+
+        // The binary data is followed by a newline and OK
+        read_line(stream)?;
+        if read_line(stream)? != "OK" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "expected OK after binary data"));
+        }
+
+        if chunk == 0 || size.map_or(true, |size| data.len() >= size) {
+            return Ok(Some(data));
+        }
+    }
+}
+
+/// Logs in and reads the picture for the song at `uri`, first from a cover file next to it and
+/// then from its tags.
+fn read_art<S: Read + Write>(stream: S, password: Option<String>, uri: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut stream = BufReader::new(stream);
+
+    if !read_line(&mut stream)?.starts_with("OK MPD ") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an MPD server"));
+    }
+
+    if let Some(password) = password {
+        writeln!(stream.get_mut(), "password {}", quote(&password))?;
+        read_line(&mut stream)?;
+    }
+
+    for command in &[ "albumart", "readpicture" ] {
+        if let Some(data) = read_binary(&mut stream, command, uri)? {
+            return Ok(Some(data));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Fetches the album art for the song at `uri` on a connection of its own, so the idling one
+/// isn't held up by it.
+pub fn album_art(config: &Config, uri: &str) -> io::Result<Option<Vec<u8>>> {
+    let (address, password) = resolve(&config.mpd, env::var("MPD_HOST").ok(), env::var("MPD_PORT").ok());
+    read_art(MpdStream::connect(&address)?, password, uri)
+}
+
+fn connect(address: &Address, password: &Option<String>) -> mpd::error::Result<mpd::Client<MpdStream>> {
+    let mut conn = mpd::Client::new(MpdStream::connect(address)?)?;
+    if let Some(ref password) = *password {
+        conn.login(password)?;
+    }
+
+    Ok(conn)
+}
+
+/// Runs the commands sent through the remote on a connection of their own. The watching one is
+/// idling most of the time, and interrupting that with `noidle` loses commands sent just before it
+/// starts. Whatever the commands change wakes up the watching connection instead. Reconnects after
+/// errors, and stops once the remote is dropped.
+fn run_commands(address: Address, password: Option<String>, commands: mpsc::Receiver<Command>) {
+    let mut conn = None;
+
+    for command in commands {
+        let res = match conn.take() {
+            Some(conn) => Ok(conn),
+            None       => connect(&address, &password),
+        }.and_then(|mut conn| run(&mut conn, command).map(|_| conn));
+
+        match res {
+            Ok(client) => conn = Some(client),
+            Err(e)     => println!("failed to run an MPD command: {}", e),
+        }
+    }
+}
+
+/// Emits the current song every time the player state changes, the queue when it or the saved
+/// playlists change and the options when they or the outputs change, until the connection is
+/// lost. Commands sent through the remote run on a thread of their own.
+pub fn watch<F>(config: &Config, emit: F) -> mpd::error::Result<()>
+where
+    F: Fn(Event),
+{
+    let (address, password) = resolve(&config.mpd, env::var("MPD_HOST").ok(), env::var("MPD_PORT").ok());
+
+    let mut conn = connect(&address, &password)?;
+
+    let (commands_sx, commands) = mpsc::channel();
+    thread::spawn(move || run_commands(address, password, commands));
+    emit(Event::Connected(Remote { commands: commands_sx }));
+
+    let subsystems = [
+        mpd::Subsystem::Player, mpd::Subsystem::Mixer, mpd::Subsystem::Queue, mpd::Subsystem::Playlist,
+        mpd::Subsystem::Options, mpd::Subsystem::Output,
+    ];
+
+    let mut changed = subsystems.to_vec();
+    loop {
+        emit(Event::Song(Some(current_song(&mut conn)?)));
+
+        // The current song moving along also moves the part of the queue that's shown
+        let queue_changed = [ mpd::Subsystem::Player, mpd::Subsystem::Queue, mpd::Subsystem::Playlist ]
+            .iter().any(|subsystem| changed.contains(subsystem));
+        if queue_changed {
+            emit(Event::Queue(read_queue(&mut conn)?));
+        }
+
+        if changed.contains(&mpd::Subsystem::Options) || changed.contains(&mpd::Subsystem::Output) {
+            emit(Event::Options(read_options(&mut conn)?));
+        }
+
+        changed = conn.wait(&subsystems)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use super::*;
+
+    fn config(host: Option<&str>, port: Option<u16>, password: Option<&str>) -> MpdConfig {
+        MpdConfig {
+            host:     host.map(String::from),
+            port,
+            password: password.map(String::from),
+        }
+    }
+
+    fn tcp(host: &str, port: u16) -> Address {
+        Address::Tcp(host.to_string(), port)
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(resolve(&config(None, None, None), None, None), (tcp("localhost", 6600), None));
+    }
+
+    #[test]
+    fn config_over_environment() {
+        let env = || (Some("secret@music.lan".to_string()), Some("6601".to_string()));
+
+        let (host, port) = env();
+        assert_eq!(resolve(&config(None, None, None), host, port), (tcp("music.lan", 6601), Some("secret".to_string())));
+
+        let (host, port) = env();
+        assert_eq!(resolve(&config(Some("nas"), Some(6602), Some("other")), host, port), (tcp("nas", 6602), Some("other".to_string())));
+    }
+
+    #[test]
+    fn unix_socket() {
+        let (address, password) = resolve(&config(Some("p@ss@/run/mpd/socket"), None, None), None, Some("6601".to_string()));
+
+        assert_eq!(address, Address::Unix(PathBuf::from("/run/mpd/socket")));
+        assert_eq!(password, Some("p@ss".to_string()));
+    }
+
+    /// Answers each expected command with its response, after greeting like MPD does.
+    fn fake_mpd<R: AsRef<str> + Send + 'static>(script: Vec<(&'static str, R)>) -> UnixStream {
+        let (client, server) = UnixStream::pair().unwrap();
+
+        thread::spawn(move || {
+            let mut server = BufReader::new(server);
+            server.get_mut().write_all(b"OK MPD 0.22.0\n").unwrap();
+
+            for (command, response) in script {
+                let mut line = String::new();
+                server.read_line(&mut line).unwrap();
+                assert_eq!(line.trim_end(), command);
+                server.get_mut().write_all(response.as_ref().as_bytes()).unwrap();
+            }
+        });
+
+        client
+    }
+
+    fn fake_client(script: Vec<(&'static str, String)>) -> mpd::Client<MpdStream> {
+        mpd::Client::new(MpdStream::Unix(fake_mpd(script))).unwrap()
+    }
+
+    /// A status reply, playing the song at `song` if there is one.
+    fn status(volume: i8, song: Option<u32>, queue_len: u32) -> String {
+        let mut status = format!(
+            "volume: {}\nrepeat: 0\nrandom: 1\nsingle: 0\nconsume: 1\nplaylist: 7\nplaylistlength: {}\n\
+             mixrampdb: 0.000000\nstate: {}\n",
+            volume, queue_len, if song.is_some() { "play" } else { "stop" },
+        );
+        if let Some(pos) = song {
+            status += &format!("song: {}\nsongid: {}\n", pos, pos + 1);
+        }
+
+        status + "OK\n"
+    }
+
+    /// A playlistinfo reply for the songs at `positions`, with only titles.
+    fn songs(positions: Range<u32>) -> String {
+        positions.map(|pos| format!("file: music/{}.flac\nTitle: Song {}\nPos: {}\nId: {}\n", pos, pos, pos, pos + 1))
+            .collect::<String>() + "OK\n"
+    }
+
+    const PLAYLISTS: &str = "playlist: Road trip\nLast-Modified: 2020-05-01T12:00:00Z\nOK\n";
+
+    #[test]
+    fn queue_around_current_song() {
+        // Five songs before the current one, and the rest of the queue as it's shorter than 15
+        let mut conn = fake_client(vec![
+            ("status",            status(50, Some(10), 12)),
+            ("playlistinfo 5:12", songs(5 .. 12)),
+            ("listplaylists",     PLAYLISTS.to_string()),
+        ]);
+
+        let queue = read_queue(&mut conn).unwrap();
+        assert_eq!(queue.current, Some(10));
+        assert_eq!(queue.songs.iter().map(|&(pos, _)| pos).collect::<Vec<_>>(), (5 .. 12).collect::<Vec<_>>());
+        assert_eq!(queue.songs[0].1, "Song 5");
+        assert_eq!(queue.playlists, vec![ "Road trip".to_string() ]);
+    }
+
+    #[test]
+    fn queue_from_start() {
+        // Near the start there are fewer songs before the current one, but still 15 in all
+        let mut conn = fake_client(vec![
+            ("status",            status(50, Some(2), 40)),
+            ("playlistinfo 0:15", songs(0 .. 15)),
+            ("listplaylists",     "OK\n".to_string()),
+        ]);
+
+        let queue = read_queue(&mut conn).unwrap();
+        assert_eq!(queue.current, Some(2));
+        assert_eq!(queue.songs.len(), QUEUE_SHOWN as usize);
+
+        // An empty queue isn't asked for at all
+        let mut conn = fake_client(vec![
+            ("status",        status(50, None, 0)),
+            ("listplaylists", PLAYLISTS.to_string()),
+        ]);
+
+        let queue = read_queue(&mut conn).unwrap();
+        assert_eq!((queue.current, queue.songs.len()), (None, 0));
+    }
+
+    #[test]
+    fn reads_options() {
+        let mut conn = fake_client(vec![
+            ("status",  status(50, None, 0)),
+            ("outputs", "outputid: 0\noutputname: Speakers\nplugin: alsa\noutputenabled: 1\n\
+                         outputid: 1\noutputname: Stream\nplugin: httpd\noutputenabled: 0\nOK\n".to_string()),
+        ]);
+
+        assert_eq!(read_options(&mut conn).unwrap(), Options {
+            random:  true,
+            repeat:  false,
+            single:  false,
+            consume: true,
+            outputs: vec![ (0, "Speakers".to_string(), true), (1, "Stream".to_string(), false) ],
+        });
+    }
+
+    #[test]
+    fn runs_commands() {
+        let ok = || "OK\n".to_string();
+        let mut conn = fake_client(vec![
+            ("pause",              ok()),
+            ("next",               ok()),
+            ("previous",           ok()),
+            ("play 3",             ok()),
+            ("clear",              ok()),
+            (r#"load "Road trip""#, ok()),
+            ("play",               ok()),
+            ("random 1",           ok()),
+            ("repeat 0",           ok()),
+            ("single 1",           ok()),
+            ("consume 0",          ok()),
+            ("enableoutput 1",     ok()),
+            ("disableoutput 0",    ok()),
+        ]);
+
+        let commands = vec![
+            Command::TogglePause,
+            Command::Next,
+            Command::Previous,
+            Command::Play(3),
+            Command::LoadPlaylist("Road trip".to_string()),
+            Command::SetOption(PlayOption::Random, true),
+            Command::SetOption(PlayOption::Repeat, false),
+            Command::SetOption(PlayOption::Single, true),
+            Command::SetOption(PlayOption::Consume, false),
+            Command::SetOutput(1, true),
+            Command::SetOutput(0, false),
+        ];
+        for command in commands {
+            run(&mut conn, command).unwrap();
+        }
+    }
+
+    #[test]
+    fn changes_volume() {
+        // Clamped to 0-100, and not set at all when MPD has no mixer and reports -1
+        let mut conn = fake_client(vec![
+            ("status",     status(98, None, 0)),
+            ("setvol 100", "OK\n".to_string()),
+            ("status",     status(3, None, 0)),
+            ("setvol 0",   "OK\n".to_string()),
+            ("status",     status(-1, None, 0)),
+            ("next",       "OK\n".to_string()),
+        ]);
+
+        for &delta in &[ 5, -5, 5 ] {
+            run(&mut conn, Command::Volume(delta)).unwrap();
+        }
+        run(&mut conn, Command::Next).unwrap();
+    }
+
+    #[test]
+    fn reads_album_art_in_chunks() {
+        let stream = fake_mpd(vec![
+            (r#"password "secret""#,             "OK\n"),
+            (r#"albumart "dir/song.flac" 0"#,    "ACK [50@0] {albumart} No file exists\n"),
+            (r#"readpicture "dir/song.flac" 0"#, "size: 6\ntype: image/png\nbinary: 4\nabcd\nOK\n"),
+            (r#"readpicture "dir/song.flac" 4"#, "size: 6\ntype: image/png\nbinary: 2\nef\nOK\n"),
+        ]);
+
+        assert_eq!(read_art(stream, Some("secret".to_string()), "dir/song.flac").unwrap(), Some(b"abcdef".to_vec()));
+    }
+
+    #[test]
+    fn no_album_art() {
+        let stream = fake_mpd(vec![
+            (r#"albumart "\"quoted\".mp3" 0"#,    "ACK [50@0] {albumart} No file exists\n"),
+            (r#"readpicture "\"quoted\".mp3" 0"#, "OK\n"),
+        ]);
+
+        assert_eq!(read_art(stream, None, "\"quoted\".mp3").unwrap(), None);
+    }
+}
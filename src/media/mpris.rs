@@ -0,0 +1,412 @@
+extern crate dbus;
+extern crate time;
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+use self::dbus::{BusType, Connection, Message, MessageType};
+use self::dbus::arg::{RefArg, Variant};
+use self::dbus::stdintf::org_freedesktop_dbus::Properties;
+
+use super::{Command, Event, PlayState, Remote, Song};
+
+const BUS:     &str = "org.freedesktop.DBus";
+const PREFIX:  &str = "org.mpris.MediaPlayer2.";
+const PATH:    &str = "/org/mpris/MediaPlayer2";
+const PLAYER:  &str = "org.mpris.MediaPlayer2.Player";
+const TIMEOUT: i32  = 2000;
+
+/// The players on the bus by unique name, ordered from least to most recently active. The last
+/// one is shown and controlled.
+struct Players(Vec<String>);
+
+impl Players {
+    fn list(conn: &Connection) -> Result<Players, dbus::Error> {
+        let msg = Message::new_method_call(BUS, "/org/freedesktop/DBus", BUS, "ListNames").unwrap();
+        let names: Vec<String> = conn.send_with_reply_and_block(msg, TIMEOUT)?.get1().unwrap_or_default();
+
+        let mut players = vec![];
+        for name in names.iter().filter(|name| name.starts_with(PREFIX)) {
+            let msg = Message::new_method_call(BUS, "/org/freedesktop/DBus", BUS, "GetNameOwner").unwrap()
+                .append1(name.as_str());
+
+            if let Some(owner) = conn.send_with_reply_and_block(msg, TIMEOUT).ok().and_then(|reply| reply.get1::<String>()) {
+                let playing = conn.with_path(owner.as_str(), PATH, TIMEOUT)
+                    .get::<String>(PLAYER, "PlaybackStatus")
+                    .map_or(false, |status| status == "Playing");
+
+                players.push((playing, owner));
+            }
+        }
+
+        // Without any history, the ones that are playing count as the most recently active
+        players.sort_by_key(|&(playing, _)| playing);
+        Ok(Players(players.into_iter().map(|(_, owner)| owner).collect()))
+    }
+
+    fn active(&self) -> Option<&str> {
+        self.0.last().map(String::as_str)
+    }
+
+    /// Keeps track of players coming, going and starting to play. Returns whether the song to
+    /// show may have changed.
+    fn handle(&mut self, msg: &Message) -> Result<bool, dbus::Error> {
+        if msg.msg_type() != MessageType::Signal { return Ok(false) }
+
+        let member = msg.member().map(|member| member.to_string()).unwrap_or_default();
+        let sender = msg.sender().map(|sender| sender.to_string()).unwrap_or_default();
+        let active = self.active().map(String::from);
+
+        match member.as_str() {
+            "Disconnected" => {
+                return Err(dbus::Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "disconnected from the bus"));
+            },
+
+            "NameOwnerChanged" => {
+                let (name, old, new) = match msg.get3::<String, String, String>() {
+                    (Some(name), Some(old), Some(new)) => (name, old, new),
+                    _                                  => return Ok(false),
+                };
+
+                if !name.starts_with(PREFIX) { return Ok(false) }
+
+                // New players don't take over until they start playing
+                self.0.retain(|owner| *owner != old);
+                if !new.is_empty() {
+                    self.0.insert(0, new);
+                }
+            },
+
+            "PropertiesChanged" => {
+                let (_, changed) = msg.get2::<String, HashMap<String, Variant<Box<dyn RefArg>>>>();
+                let started = changed
+                    .and_then(|changed| changed.get("PlaybackStatus").map(|status| status.0.as_str() == Some("Playing")))
+                    .unwrap_or(false);
+
+                if started && self.0.contains(&sender) {
+                    self.0.retain(|owner| *owner != sender);
+                    self.0.push(sender.clone());
+                }
+
+                return Ok(self.active() == Some(sender.as_str()));
+            },
+
+            // The elapsed time jumped
+            "Seeked" => return Ok(self.active() == Some(sender.as_str())),
+
+            _ => return Ok(false),
+        }
+
+        Ok(self.active().map(String::from) != active)
+    }
+}
+
+/// All strings in a value, which may be a string, a number or an array of them, possibly in
+/// variants.
+fn strings(arg: &dyn RefArg) -> Vec<String> {
+    if let Some(s) = arg.as_str() {
+        return vec![ s.to_string() ];
+    }
+    if let Some(n) = arg.as_i64() {
+        return vec![ n.to_string() ];
+    }
+
+    arg.as_iter().map_or(vec![], |items| items.flat_map(|item| strings(item)).collect())
+}
+
+/// Decodes the %XX escapes in a URL's path.
+fn percent_decode(path: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest  = path.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = if byte == b'%' && tail.len() >= 2 {
+            ::std::str::from_utf8(&tail[..2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => { bytes.push(byte); rest = &tail[2..]; },
+            None       => { bytes.push(byte); rest = tail; },
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// MPRIS metadata keys by the MPD tag names songs use.
+const TAGS: &[(&str, &str)] = &[
+    ("artist",      "xesam:artist"),
+    ("albumartist", "xesam:albumArtist"),
+    ("album",       "xesam:album"),
+    ("title",       "xesam:title"),
+    ("track",       "xesam:trackNumber"),
+    ("date",        "xesam:contentCreated"),
+    ("genre",       "xesam:genre"),
+    ("composer",    "xesam:composer"),
+];
+
+/// Reads what a player is playing, or `None` if it doesn't say.
+fn current_song(conn: &Connection, owner: &str) -> Result<Option<Song>, dbus::Error> {
+    let player = conn.with_path(owner, PATH, TIMEOUT);
+    let props  = player.get_all(PLAYER)?;
+    let synced = time::precise_time_s();
+
+    let state = match props.get("PlaybackStatus").and_then(|status| status.0.as_str()) {
+        Some("Playing") => PlayState::Playing,
+        Some("Paused")  => PlayState::Paused,
+        Some("Stopped") => PlayState::Stopped,
+        _               => return Ok(None),
+    };
+
+    // Metadata is a dictionary, which iterates as keys and values in turn
+    let mut metadata = HashMap::new();
+    if let Some(items) = props.get("Metadata").and_then(|metadata| metadata.0.as_iter()) {
+        let items = items.collect::<Vec<_>>();
+        for pair in items.chunks(2).filter(|pair| pair.len() == 2) {
+            if let Some(key) = pair[0].as_str() {
+                metadata.insert(key.to_string(), pair[1]);
+            }
+        }
+    }
+
+    let tag = |key: &str| metadata.get(key).map_or(String::new(), |value| strings(*value).join(", "));
+
+    let mut tags = TAGS.iter()
+        .map(|&(tag_name, key)| (tag_name.to_string(), tag(key)))
+        .filter(|&(_, ref value)| !value.is_empty())
+        .collect::<HashMap<_, _>>();
+
+    // Dates are full timestamps like 2007-04-29T14:35:51, where MPD usually has the day at most
+    if let Some(date) = tags.get_mut("date") {
+        let day = date.find('T').unwrap_or(date.len());
+        date.truncate(day);
+    }
+    let url = tag("xesam:url");
+    if let Some(name) = url.rsplit('/').next().filter(|name| !name.is_empty()) {
+        tags.insert("file".to_string(), name.to_string());
+    }
+    let uri = if url.starts_with("file://") { percent_decode(&url[7..]) } else { String::new() };
+
+    // Lengths and positions are in microseconds, and some players send the length unsigned
+    let duration = metadata.get("mpris:length")
+        .and_then(|length| length.as_i64().or_else(|| length.as_u64().map(|length| length as i64)))
+        .unwrap_or(0);
+    let elapsed = player.get::<i64>(PLAYER, "Position").unwrap_or(0);
+
+    let volume = props.get("Volume")
+        .and_then(|volume| volume.0.as_f64())
+        .map_or(-1, |volume| (volume * 100.0).round() as i8);
+
+    Ok(Some(Song {
+        tags,
+        uri,
+        state,
+        volume,
+        elapsed:  elapsed as f64 / 1_000_000.0,
+        duration: duration as f64 / 1_000_000.0,
+        synced,
+    }))
+}
+
+fn run(conn: &Connection, owner: &str, command: Command) -> Result<(), dbus::Error> {
+    let method = match command {
+        Command::TogglePause   => "PlayPause",
+        Command::Next          => "Next",
+        Command::Previous      => "Previous",
+        Command::Volume(delta) => {
+            let player = conn.with_path(owner, PATH, TIMEOUT);
+            let volume = player.get::<f64>(PLAYER, "Volume")?;
+
+            return player.set(PLAYER, "Volume", (volume + delta as f64 / 100.0).max(0.0).min(1.0));
+        },
+        // MPRIS players don't share their queue or outputs, so there's nothing to pick from
+        Command::Play(_) | Command::LoadPlaylist(_) => return Ok(()),
+        Command::SetOption(..) | Command::SetOutput(..) => return Ok(()),
+    };
+
+    let msg = Message::new_method_call(owner, PATH, PLAYER, method).unwrap();
+    conn.send_with_reply_and_block(msg, TIMEOUT).map(|_| ())
+}
+
+/// Emits what the most recently active player is playing whenever that changes, and runs the
+/// commands sent through the remote on it, until the connection to the bus is lost. Connects to
+/// the session bus unless given another address.
+pub fn watch<F>(address: Option<&str>, emit: F) -> Result<(), dbus::Error>
+where
+    F: Fn(Event),
+{
+    let conn = match address {
+        Some(address) => {
+            let conn = Connection::open_private(address)?;
+            conn.register()?;
+            conn
+        },
+        None => Connection::get_private(BusType::Session)?,
+    };
+
+    conn.add_match(&format!("type='signal',interface='{}',member='NameOwnerChanged'", BUS))?;
+    conn.add_match(&format!("type='signal',path='{}',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'", PATH))?;
+    conn.add_match(&format!("type='signal',path='{}',interface='{}',member='Seeked'", PATH, PLAYER))?;
+
+    let mut players = Players::list(&conn)?;
+
+    let (commands_sx, commands) = mpsc::channel();
+    emit(Event::Connected(Remote { commands: commands_sx }));
+
+    let mut changed = true;
+    loop {
+        for command in commands.try_iter() {
+            if let Some(owner) = players.active() {
+                // The player may have just gone away, which is no reason to reconnect
+                if let Err(e) = run(&conn, owner, command) {
+                    println!("failed to control {}: {}", owner, e);
+                }
+            }
+        }
+
+        if changed {
+            let song = players.active().map_or(Ok(None), |owner| current_song(&conn, owner));
+            emit(Event::Song(song.unwrap_or(None)));
+            changed = false;
+        }
+
+        // Returns after 100ms without messages, to run commands
+        for msg in conn.incoming(100) {
+            changed |= players.handle(&msg)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader};
+    use std::process::{self, Child, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::dbus::NameFlag;
+    use super::*;
+
+    /// A bus of its own, killed when dropped.
+    struct Bus {
+        daemon:  Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Bus {
+            let daemon = process::Command::new("dbus-daemon")
+                .args(&[ "--session", "--nofork", "--print-address" ])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("failed to start dbus-daemon");
+
+            let mut bus = Bus { daemon, address: String::new() };
+
+            let mut address = String::new();
+            BufReader::new(bus.daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+            assert!(!address.trim().is_empty(), "dbus-daemon exited without printing its address");
+
+            bus.address = address.trim().to_string();
+            bus
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn properties(playing: bool) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+        let mut metadata: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        metadata.insert("xesam:title".to_string(),  Variant(Box::new("Song".to_string())));
+        metadata.insert("xesam:artist".to_string(), Variant(Box::new(vec![ "Artist".to_string() ])));
+        metadata.insert("mpris:length".to_string(), Variant(Box::new(180_000_000i64)));
+
+        let status = if playing { "Playing" } else { "Paused" };
+
+        let mut props: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        props.insert("PlaybackStatus".to_string(), Variant(Box::new(status.to_string())));
+        props.insert("Volume".to_string(),         Variant(Box::new(0.5)));
+        props.insert("Metadata".to_string(),       Variant(Box::new(metadata)));
+        props
+    }
+
+    /// Plays a song, 30 seconds in, that can be paused and resumed.
+    fn fake_player(address: &str, ready: mpsc::Sender<()>) {
+        let conn = Connection::open_private(address).unwrap();
+        conn.register().unwrap();
+        conn.register_name("org.mpris.MediaPlayer2.fake", NameFlag::ReplaceExisting as u32).unwrap();
+        ready.send(()).unwrap();
+
+        let mut playing = true;
+        loop {
+            for msg in conn.incoming(100) {
+                if msg.msg_type() != MessageType::MethodCall { continue }
+
+                let member = msg.member().map(|member| member.to_string()).unwrap_or_default();
+                let reply = match member.as_str() {
+                    "GetAll"    => msg.method_return().append1(properties(playing)),
+                    "Get"       => msg.method_return().append1(Variant(30_000_000i64)),
+                    "PlayPause" => {
+                        playing = !playing;
+
+                        let changed = properties(playing).into_iter()
+                            .filter(|&(ref key, _)| key == "PlaybackStatus")
+                            .collect::<HashMap<_, _>>();
+                        let signal = Message::new_signal(PATH, "org.freedesktop.DBus.Properties", "PropertiesChanged").unwrap()
+                            .append3(PLAYER, changed, Vec::<String>::new());
+                        conn.send(signal).unwrap();
+
+                        msg.method_return()
+                    },
+                    _ => msg.method_return(),
+                };
+
+                conn.send(reply).unwrap();
+            }
+        }
+    }
+
+    fn next_song(rx: &mpsc::Receiver<Event>) -> Song {
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Event::Song(Some(song)) => song,
+            event                   => panic!("expected a song, got {:?}", event),
+        }
+    }
+
+    // Needs dbus-daemon, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn follows_fake_player() {
+        let bus = Bus::start();
+        let address = bus.address.clone();
+
+        let (ready_sx, ready) = mpsc::channel();
+        let player_address = address.clone();
+        thread::spawn(move || fake_player(&player_address, ready_sx));
+        ready.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let (sx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = watch(Some(&address), |event| { let _ = sx.send(event); });
+        });
+
+        let remote = match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Event::Connected(remote) => remote,
+            event                    => panic!("expected a connection, got {:?}", event),
+        };
+
+        let song = next_song(&rx);
+        assert_eq!((song.tags["artist"].as_str(), song.tags["title"].as_str()), ("Artist", "Song"));
+        assert_eq!((song.state, song.volume), (PlayState::Playing, 50));
+        assert_eq!((song.elapsed, song.duration), (30.0, 180.0));
+
+        remote.send(Command::TogglePause);
+        assert_eq!(next_song(&rx).state, PlayState::Paused);
+    }
+}
@@ -10,7 +10,9 @@ pub enum MonitorMsg {
     SetRelevance(Relevance),
 }
 
-#[derive(Clone, Debug)]
+/// Whether to show something in the bar or only in the popup.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Relevance {
     Urgent,
     Background,
@@ -0,0 +1,108 @@
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use super::{Backend, SETTLE_TIME};
+
+pub const SOCKET: &str = "/var/run/acpid.socket";
+
+/// Wakes up on acpid's events, like the AC adapter being plugged in. Doesn't hear about the
+/// batteries draining, which the interval takes care of.
+pub struct Acpid {
+    events:   BufReader<UnixStream>,
+    interval: Duration,
+}
+
+impl Acpid {
+    pub fn connect(path: &Path, interval: Duration) -> io::Result<Acpid> {
+        Ok(Acpid {
+            events: BufReader::new(UnixStream::connect(path)?),
+            interval,
+        })
+    }
+
+    /// Reads an event, returning whether there was one before the timeout.
+    fn next_event(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.events.get_mut().set_read_timeout(Some(timeout))?;
+
+        let mut line = String::new();
+        match self.events.read_line(&mut line) {
+            Ok(0) => Err(io::Error::new(ErrorKind::UnexpectedEof, "acpid closed the connection")),
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Backend for Acpid {
+    fn wait(&mut self) -> io::Result<()> {
+        if self.next_event(self.interval)? {
+            // Events come in bursts, the rest of which is waited out
+            while self.next_event(Duration::from_millis(SETTLE_TIME))? {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn wakes_up_on_events() {
+        let path = env::temp_dir().join(format!("obsidian-acpid-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Sends a burst of events like plugging in the AC adapter does, and hangs up when told to
+        let (hang_up, hung_up) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"ac_adapter ACPI0003:00 00000080 00000001\n").unwrap();
+            thread::sleep(Duration::from_millis(SETTLE_TIME / 2));
+            stream.write_all(b"battery PNP0C0A:00 00000080 00000001\n").unwrap();
+            let _ = hung_up.recv();
+        });
+
+        let mut acpid = Acpid::connect(&path, Duration::from_secs(5)).unwrap();
+        let start = Instant::now();
+        acpid.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // The whole burst was waited out
+        assert!(!acpid.next_event(Duration::from_millis(50)).unwrap());
+
+        hang_up.send(()).unwrap();
+        assert!(acpid.wait().is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn times_out() {
+        let path = env::temp_dir().join(format!("obsidian-acpid-quiet-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut acpid = Acpid::connect(&path, Duration::from_millis(100)).unwrap();
+        let _stream = listener.accept().unwrap();
+
+        let start = Instant::now();
+        acpid.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,570 @@
+extern crate time;
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use config::{BatteryBackend, BatteryThreshold, Config};
+use relm_core::Sender;
+
+use ::monitor::*;
+use ::template::Template;
+
+mod acpid;
+mod poll;
+mod uevent;
+
+/// Waits for the power supplies to change.
+pub trait Backend {
+    /// Blocks until something may have changed, or the interval it was opened with passed, since
+    /// not every change is announced. Errors mean the backend stopped working.
+    fn wait(&mut self) -> io::Result<()>;
+}
+
+/// How long to wait for more events after one, so the readings are of the settled state.
+const SETTLE_TIME: u64 = 200;
+
+/// Opens the configured backend. Automatically that's acpid if it's running, netlink uevents if
+/// they can be listened to, and polling otherwise.
+fn open_backend(config: &Config) -> Box<dyn Backend + Send> {
+    let interval = Duration::from_secs(config.battery.interval);
+
+    let acpid  = || acpid::Acpid::connect(Path::new(acpid::SOCKET), interval).map(|backend| Box::new(backend) as Box<dyn Backend + Send>);
+    let uevent = || uevent::Uevent::open(interval).map(|backend| Box::new(backend) as Box<dyn Backend + Send>);
+
+    let res = match config.battery.backend {
+        BatteryBackend::Acpid  => acpid(),
+        BatteryBackend::Uevent => uevent(),
+        BatteryBackend::Poll   => return Box::new(poll::Poll::new(interval)),
+        BatteryBackend::Auto   => acpid().or_else(|_| uevent()),
+    };
+
+    res.unwrap_or_else(|e| {
+        println!("can't watch the battery for changes ({}), polling it instead", e);
+        Box::new(poll::Poll::new(interval))
+    })
+}
+
+/// How many readings of the power draw to average the time remaining over.
+const RATE_SAMPLES: usize = 6;
+
+/// All batteries in the system taken together, like one big battery.
+pub struct Battery {
+    /// Where to look for the power supplies again, which come and go with hot-swapped batteries.
+    root:      PathBuf,
+    batteries: Vec<PathBuf>,
+    adapters:  Vec<PathBuf>,
+    capacity:  u8,
+    charging:  bool,
+    /// Energy left and at most, in µWh.
+    energy:      f64,
+    energy_full: f64,
+    /// The last few readings of the power going in or out, in µW, for a steady estimate.
+    rates:       VecDeque<f64>,
+    /// Which of the configured thresholds the batteries are below.
+    crossed:     Vec<bool>,
+}
+
+fn read_value(path: &Path) -> io::Result<String> {
+    let mut s = String::with_capacity(16);
+    File::open(path)?.read_to_string(&mut s)?;
+    Ok(s.trim().to_string())
+}
+
+fn read_number(path: &Path) -> io::Result<f64> {
+    read_value(path)?.parse().map_err(|_| io::Error::new(ErrorKind::InvalidData, format!("not a number in {}", path.display())))
+}
+
+/// What a battery says about its charge.
+enum Reading {
+    /// Energy left, at most, and the power going in or out, which is 0 if it's unknown.
+    Energy(f64, f64, f64),
+    /// Only the percentage, from batteries without energy or charge readings.
+    Capacity(f64),
+}
+
+/// Reads how much energy a battery has. Batteries that only report charge and current have them
+/// converted at their design voltage, so they add up with the others.
+fn read_supply(path: &Path) -> io::Result<Reading> {
+    if let (Ok(now), Ok(full)) = (read_number(&path.join("energy_now")), read_number(&path.join("energy_full"))) {
+        let power = read_number(&path.join("power_now")).unwrap_or(0.0);
+        return Ok(Reading::Energy(now, full, power.abs()));
+    }
+
+    if let (Ok(now), Ok(full)) = (read_number(&path.join("charge_now")), read_number(&path.join("charge_full"))) {
+        let current = read_number(&path.join("current_now")).unwrap_or(0.0);
+        let voltage = read_number(&path.join("voltage_min_design")).unwrap_or(1_000_000.0) / 1_000_000.0;
+        return Ok(Reading::Energy(now * voltage, full * voltage, current.abs() * voltage));
+    }
+
+    read_number(&path.join("capacity")).map(Reading::Capacity)
+}
+
+/// Whether a battery is in. Hot-swappable ones say so, others disappear when taken out.
+fn is_present(path: &Path) -> bool {
+    path.exists() && read_value(&path.join("present")).map_or(true, |present| present != "0")
+}
+
+/// Lists the batteries that are in and the AC adapters among the power supplies in `root`.
+/// Batteries of devices like mice don't power the system, so they're left out.
+fn find_supplies(root: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut supplies = fs::read_dir(root)?.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect::<Vec<_>>();
+    supplies.sort();
+
+    let mut batteries = vec![];
+    let mut adapters  = vec![];
+    for supply in supplies {
+        match read_value(&supply.join("type")).as_ref().map(String::as_str) {
+            Ok("Battery") if read_value(&supply.join("scope")).ok().map_or(true, |scope| scope != "Device") => {
+                if is_present(&supply) {
+                    batteries.push(supply);
+                }
+            },
+            Ok("Mains") => adapters.push(supply),
+            _           => (),
+        }
+    }
+
+    Ok((batteries, adapters))
+}
+
+/// Formats seconds as hours and minutes, like 2:05.
+fn format_duration(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as u64;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Runs a threshold's command in the background.
+fn run_command(command: &str) {
+    match Command::new("sh").arg("-c").arg(command).spawn() {
+        // Waited for so it doesn't linger as a zombie
+        Ok(mut child) => { thread::spawn(move || child.wait()); },
+        Err(e)        => println!("failed to run `{}`: {}", command, e),
+    }
+}
+
+/// The color name and relevance from the crossed thresholds, lowest first, each from the lowest
+/// one that sets it. Without crossed thresholds, there's nothing to draw attention to.
+fn threshold_style<'a>(active: &[&'a BatteryThreshold]) -> (Option<&'a str>, Relevance) {
+    let color = active.iter().filter_map(|threshold| threshold.color.as_ref()).next().map(String::as_str);
+
+    let relevance = if active.is_empty() {
+        Relevance::Background
+    } else {
+        active.iter().filter_map(|threshold| threshold.relevance).next().unwrap_or(Relevance::Urgent)
+    };
+
+    (color, relevance)
+}
+
+impl Battery {
+    /// Finds the system's batteries and AC adapters in the default sysfs location.
+    pub fn new() -> Result<Battery, String> {
+        Battery::discover(Path::new("/sys/class/power_supply"))
+    }
+
+    /// Finds the batteries and AC adapters among the power supplies in `root`. Fails if there are
+    /// no batteries.
+    pub fn discover(root: &Path) -> Result<Battery, String> {
+        let (batteries, adapters) = find_supplies(root).map_err(|e| format!("can't list {}: {}", root.display(), e))?;
+
+        if batteries.is_empty() {
+            return Err(format!("no batteries in {}", root.display()));
+        }
+
+        let mut bat = Battery {
+            root: root.to_path_buf(),
+            batteries,
+            adapters,
+            capacity:    0,
+            charging:    false,
+            energy:      0.0,
+            energy_full: 0.0,
+            rates:       VecDeque::with_capacity(RATE_SAMPLES),
+            crossed:     vec![],
+        };
+        bat.read().map_err(|e| format!("can't read the battery: {}", e))?;
+
+        Ok(bat)
+    }
+
+    /// Reads the charge of all batteries, and whether they're charging. That's when an AC adapter
+    /// is online, or a battery says so if there are no adapters to ask. The power supplies are
+    /// looked for again every time, since batteries can be taken out and put back in.
+    fn read(&mut self) -> io::Result<()> {
+        let (batteries, adapters) = find_supplies(&self.root)?;
+        self.batteries = batteries;
+        self.adapters  = adapters;
+
+        let mut readings = vec![];
+        for battery in &self.batteries {
+            match read_supply(battery) {
+                Ok(reading) => readings.push(reading),
+                // Taken out while reading it
+                Err(ref e) if e.kind() == ErrorKind::NotFound && !is_present(battery) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        if readings.is_empty() {
+            return Err(io::Error::new(ErrorKind::NotFound, "no batteries are in"));
+        }
+
+        let (mut now, mut full, mut rate) = (0.0, 0.0, 0.0);
+        let mut percentages = vec![];
+        for reading in &readings {
+            match *reading {
+                Reading::Energy(battery_now, battery_full, battery_rate) => {
+                    now  += battery_now;
+                    full += battery_full;
+                    rate += battery_rate;
+                    percentages.push(if battery_full > 0.0 { battery_now / battery_full * 100.0 } else { 0.0 });
+                },
+                Reading::Capacity(percentage) => percentages.push(percentage),
+            }
+        }
+
+        // Without the energy of every battery, the best there is is their average, and no estimate
+        let capacity = if readings.iter().all(|reading| if let Reading::Energy(..) = *reading { true } else { false }) {
+            if full > 0.0 { now / full * 100.0 } else { 0.0 }
+        } else {
+            rate = 0.0;
+            self.rates.clear();
+            percentages.iter().sum::<f64>() / percentages.len() as f64
+        };
+
+        self.capacity    = capacity.round().max(0.0).min(100.0) as u8;
+        self.energy      = now;
+        self.energy_full = full;
+
+        let was_charging = self.charging;
+        self.charging = if self.adapters.is_empty() {
+            self.batteries.iter().any(|battery| {
+                read_value(&battery.join("status")).ok().map_or(false, |status| status == "Charging" || status == "Full")
+            })
+        } else {
+            self.adapters.iter().any(|adapter| read_value(&adapter.join("online")).ok().map_or(false, |online| online == "1"))
+        };
+
+        // Charging and discharging rates have nothing to do with each other
+        if self.charging != was_charging {
+            self.rates.clear();
+        }
+        if rate > 0.0 {
+            if self.rates.len() == RATE_SAMPLES {
+                self.rates.pop_front();
+            }
+            self.rates.push_back(rate);
+        }
+
+        Ok(())
+    }
+
+    /// Seconds until the batteries are empty, or full while charging, at the average power of the
+    /// last readings. `None` if the power isn't known or nothing is flowing.
+    fn remaining(&self) -> Option<f64> {
+        if self.rates.is_empty() { return None }
+        let rate = self.rates.iter().sum::<f64>() / self.rates.len() as f64;
+
+        let energy = if self.charging { self.energy_full - self.energy } else { self.energy };
+        if energy <= 0.0 { return None }
+
+        Some(energy / rate * 3600.0)
+    }
+
+    /// Fills in the template with `{percentage}`, `{state}` (charging, discharging or full), its
+    /// `{glyph}` and the `{remaining}` time.
+    fn text(&self, template: &Template) -> String {
+        let (state, glyph) = match (self.charging, self.capacity) {
+            (true, 100) => ("full",        "="),
+            (true, _)   => ("charging",    "▲"),
+            (false, _)  => ("discharging", "▼"),
+        };
+
+        template.render(|name| match name {
+            "percentage" => Some(self.capacity.to_string()),
+            "state"      => Some(state.to_string()),
+            "glyph"      => Some(glyph.to_string()),
+            "remaining"  => self.remaining().map(format_duration),
+            _            => None,
+        })
+    }
+
+    /// Updates which thresholds the batteries are at or below, returning the ones just crossed. A
+    /// threshold is crossed again after charging, or rising more than `hysteresis` points above it.
+    fn cross_thresholds(&mut self, thresholds: &[BatteryThreshold], hysteresis: u8) -> Vec<usize> {
+        self.crossed.resize(thresholds.len(), false);
+
+        let mut newly_crossed = vec![];
+        for (i, threshold) in thresholds.iter().enumerate() {
+            if self.charging || self.capacity as u16 > threshold.level as u16 + hysteresis as u16 {
+                self.crossed[i] = false;
+            } else if !self.crossed[i] && self.capacity <= threshold.level {
+                self.crossed[i] = true;
+                newly_crossed.push(i);
+            }
+        }
+
+        newly_crossed
+    }
+
+    /// The crossed thresholds, lowest first.
+    fn active_thresholds<'a>(&self, thresholds: &'a [BatteryThreshold]) -> Vec<&'a BatteryThreshold> {
+        let mut active = thresholds.iter().zip(&self.crossed)
+            .filter(|&(_, &crossed)| crossed)
+            .map(|(threshold, _)| threshold)
+            .collect::<Vec<_>>();
+        active.sort_by_key(|threshold| threshold.level);
+        active
+    }
+
+    fn send_state(&mut self, config: &'static Config, channel: &Sender<MonitorMsg>) {
+        for i in self.cross_thresholds(&config.battery.thresholds, config.battery.hysteresis) {
+            if let Some(ref command) = config.battery.thresholds[i].command {
+                run_command(command);
+            }
+        }
+
+        let (color, relevance) = threshold_style(&self.active_thresholds(&config.battery.thresholds));
+        let color = match (self.charging, color) {
+            (true, _)           => config.get_color("blue"),
+            (false, Some(name)) => config.get_color(name),
+            (false, None)       => config.get_color("green"),
+        };
+
+        channel.send(MonitorMsg::SetText(self.text(&config.battery.format)));
+        channel.send(MonitorMsg::SetColor(color));
+        channel.send(MonitorMsg::SetRelevance(relevance));
+    }
+}
+
+impl Monitor for Battery {
+    fn start(mut self, config: &'static Config, channel: Sender<MonitorMsg>) {
+        self.send_state(config, &channel);
+
+        thread::spawn(move || {
+            let mut backend = open_backend(config);
+
+            loop {
+                // Polling never fails, so this only falls back once
+                if let Err(e) = backend.wait() {
+                    println!("stopped watching the battery for changes ({}), polling it instead", e);
+                    backend = Box::new(poll::Poll::new(Duration::from_secs(config.battery.interval)));
+                }
+
+                if let Err(e) = self.read() {
+                    println!("failed to read the battery: {}", e);
+                }
+
+                self.send_state(config, &channel);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+    use super::*;
+
+    static SYSFS_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    /// A power_supply directory with the given supplies and their attributes.
+    pub fn fake_sysfs(supplies: &[(&str, &[(&str, &str)])]) -> PathBuf {
+        let n = SYSFS_COUNT.fetch_add(1, Ordering::SeqCst);
+        let root = env::temp_dir().join(format!("obsidian-power-supply-{}-{}", process::id(), n));
+        let _ = fs::remove_dir_all(&root);
+
+        for &(name, attributes) in supplies {
+            fs::create_dir_all(root.join(name)).unwrap();
+            for &(attribute, value) in attributes {
+                File::create(root.join(name).join(attribute)).unwrap().write_all(format!("{}\n", value).as_bytes()).unwrap();
+            }
+        }
+        fs::create_dir_all(&root).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn adds_up_batteries() {
+        let root = fake_sysfs(&[
+            ("AC",     &[ ("type", "Mains"), ("online", "0") ]),
+            ("BAT0",   &[ ("type", "Battery"), ("status", "Discharging"), ("energy_now", "20000000"), ("energy_full", "24000000") ]),
+            // 3Ah at 10V is 30Wh
+            ("BAT1",   &[ ("type", "Battery"), ("status", "Unknown"), ("charge_now", "1000000"), ("charge_full", "3000000"),
+                          ("voltage_min_design", "10000000") ]),
+            ("hidpp0", &[ ("type", "Battery"), ("scope", "Device"), ("capacity", "5") ]),
+        ]);
+
+        let battery = Battery::discover(&root).unwrap();
+        assert_eq!(battery.batteries, vec![ root.join("BAT0"), root.join("BAT1") ]);
+        assert_eq!(battery.adapters, vec![ root.join("AC") ]);
+        assert_eq!((battery.capacity, battery.charging), (56, false));
+
+        File::create(root.join("AC/online")).unwrap().write_all(b"1\n").unwrap();
+        let battery = Battery::discover(&root).unwrap();
+        assert_eq!((battery.capacity, battery.charging), (56, true));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn time_remaining() {
+        let root = fake_sysfs(&[
+            ("AC",   &[ ("type", "Mains"), ("online", "0") ]),
+            ("BAT0", &[ ("type", "Battery"), ("energy_now", "20000000"), ("energy_full", "40000000"), ("power_now", "10000000") ]),
+        ]);
+        let template = "{glyph} {percentage}%[ {remaining}]".parse::<Template>().unwrap();
+
+        let mut battery = Battery::discover(&root).unwrap();
+        assert_eq!(battery.text(&template), "▼ 50% 2:00");
+
+        // Averaged with the 10W before, so 7.5W
+        File::create(root.join("BAT0/power_now")).unwrap().write_all(b"5000000\n").unwrap();
+        battery.read().unwrap();
+        assert_eq!(battery.text(&template), "▼ 50% 2:40");
+
+        // Starts over when plugged in, with the time until it's full
+        File::create(root.join("AC/online")).unwrap().write_all(b"1\n").unwrap();
+        File::create(root.join("BAT0/power_now")).unwrap().write_all(b"40000000\n").unwrap();
+        battery.read().unwrap();
+        assert_eq!(battery.text(&template), "▲ 50% 0:30");
+
+        File::create(root.join("BAT0/power_now")).unwrap().write_all(b"0\n").unwrap();
+        File::create(root.join("BAT0/energy_now")).unwrap().write_all(b"40000000\n").unwrap();
+        let battery = Battery::discover(&root).unwrap();
+        assert_eq!(battery.text(&template), "= 100%");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn status_without_adapters() {
+        let root = fake_sysfs(&[
+            ("BAT0", &[ ("type", "Battery"), ("status", "Charging"), ("energy_now", "5"), ("energy_full", "10") ]),
+        ]);
+
+        let battery = Battery::discover(&root).unwrap();
+        assert_eq!((battery.capacity, battery.charging), (50, true));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn no_batteries() {
+        let root = fake_sysfs(&[ ("AC", &[ ("type", "Mains"), ("online", "1") ]) ]);
+
+        assert!(Battery::discover(&root).err().unwrap().starts_with("no batteries"));
+        assert!(Battery::discover(&root.join("missing")).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hot_swapping() {
+        let root = fake_sysfs(&[
+            ("AC",   &[ ("type", "Mains"), ("online", "0") ]),
+            ("BAT0", &[ ("type", "Battery"), ("present", "1"), ("energy_now", "10"), ("energy_full", "20") ]),
+            ("BAT1", &[ ("type", "Battery"), ("present", "1"), ("energy_now", "20"), ("energy_full", "20") ]),
+        ]);
+        let mut battery = Battery::discover(&root).unwrap();
+        assert_eq!(battery.capacity, 75);
+
+        // Some drivers keep the battery around, others unregister it
+        File::create(root.join("BAT1/present")).unwrap().write_all(b"0\n").unwrap();
+        battery.read().unwrap();
+        assert_eq!((battery.batteries.len(), battery.capacity), (1, 50));
+
+        fs::remove_dir_all(root.join("BAT1")).unwrap();
+        battery.read().unwrap();
+        assert_eq!((battery.batteries.len(), battery.capacity), (1, 50));
+
+        fs::create_dir(root.join("BAT1")).unwrap();
+        for &(attribute, value) in &[ ("type", "Battery"), ("energy_now", "10"), ("energy_full", "20") ] {
+            File::create(root.join("BAT1").join(attribute)).unwrap().write_all(value.as_bytes()).unwrap();
+        }
+        battery.read().unwrap();
+        assert_eq!((battery.batteries.len(), battery.capacity), (2, 50));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn capacity_only() {
+        let root = fake_sysfs(&[
+            ("BAT0", &[ ("type", "Battery"), ("status", "Discharging"), ("capacity", "80") ]),
+        ]);
+        let battery = Battery::discover(&root).unwrap();
+        assert_eq!(battery.capacity, 80);
+        assert_eq!(battery.remaining(), None);
+
+        // Averaged with the others, since there's no energy to add up
+        let mixed = fake_sysfs(&[
+            ("BAT0", &[ ("type", "Battery"), ("status", "Discharging"), ("capacity", "80") ]),
+            ("BAT1", &[ ("type", "Battery"), ("energy_now", "10"), ("energy_full", "40"), ("power_now", "5") ]),
+        ]);
+        let battery = Battery::discover(&mixed).unwrap();
+        assert_eq!(battery.capacity, 53);
+        assert_eq!(battery.remaining(), None);
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&mixed).unwrap();
+    }
+
+    #[test]
+    fn thresholds() {
+        let root = fake_sysfs(&[
+            ("BAT0", &[ ("type", "Battery"), ("status", "Discharging"), ("energy_now", "20"), ("energy_full", "100") ]),
+        ]);
+        let mut battery = Battery::discover(&root).unwrap();
+
+        let threshold = |level, color: Option<&str>, relevance| BatteryThreshold {
+            level,
+            color:   color.map(String::from),
+            relevance,
+            command: None,
+        };
+        let thresholds = [
+            threshold(15, Some("red"), None),
+            threshold(40, Some("yellow"), Some(Relevance::Background)),
+            threshold(5,  None, None),
+        ];
+
+        let mut cross = |capacity, charging| {
+            battery.capacity = capacity;
+            battery.charging = charging;
+            let crossed = battery.cross_thresholds(&thresholds, 3);
+            let active  = battery.active_thresholds(&thresholds);
+            (crossed, active.iter().map(|threshold| threshold.level).collect::<Vec<_>>(), threshold_style(&active))
+        };
+
+        let (yellow, red) = ((Some("yellow"), Relevance::Background), (Some("red"), Relevance::Background));
+
+        assert_eq!(cross(50, false), (vec![], vec![], (None, Relevance::Background)));
+        assert_eq!(cross(20, false), (vec![ 1 ], vec![ 40 ], yellow));
+        assert_eq!(cross(15, false), (vec![ 0 ], vec![ 15, 40 ], red));
+        // Hovering around a threshold doesn't cross it again
+        assert_eq!(cross(18, false), (vec![], vec![ 15, 40 ], red));
+        assert_eq!(cross(15, false), (vec![], vec![ 15, 40 ], red));
+        assert_eq!(cross(19, false), (vec![], vec![ 40 ], yellow));
+        assert_eq!(cross(14, false), (vec![ 0 ], vec![ 15, 40 ], red));
+        assert_eq!(cross(4, false),  (vec![ 2 ], vec![ 5, 15, 40 ], red));
+        // Charging starts over
+        assert_eq!(cross(4, true),   (vec![], vec![], (None, Relevance::Background)));
+        assert_eq!(cross(4, false),  (vec![ 0, 1, 2 ], vec![ 5, 15, 40 ], red));
+
+        let urgent = threshold(10, None, None);
+        assert_eq!(threshold_style(&[ &urgent ]), (None, Relevance::Urgent));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
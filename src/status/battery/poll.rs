@@ -0,0 +1,41 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use super::Backend;
+
+/// Reads the batteries every interval, for when nothing announces changes.
+pub struct Poll {
+    interval: Duration,
+}
+
+impl Poll {
+    pub fn new(interval: Duration) -> Poll {
+        Poll { interval }
+    }
+}
+
+impl Backend for Poll {
+    fn wait(&mut self) -> io::Result<()> {
+        thread::sleep(self.interval);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn sleeps_for_interval() {
+        let mut poll = Poll::new(Duration::from_millis(50));
+
+        for _ in 0 .. 2 {
+            let start = Instant::now();
+            poll.wait().unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(50));
+        }
+    }
+}
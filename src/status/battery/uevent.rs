@@ -0,0 +1,187 @@
+extern crate libc;
+
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use super::{Backend, SETTLE_TIME};
+
+/// Wakes up on the kernel's uevents about power supplies, which come when an AC adapter is
+/// plugged in or out and every so often as batteries charge or drain.
+pub struct Uevent {
+    socket:   Socket,
+    interval: Duration,
+}
+
+/// A datagram socket of any family, which std only has types for when it's a unix or IP one. Closed
+/// when dropped.
+struct Socket {
+    fd: RawFd,
+}
+
+impl Socket {
+    /// Receives a datagram, returning its length or `None` if there was none before the timeout.
+    fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        // A zero timeout would wait forever
+        let timeout = timeout.max(Duration::from_micros(1));
+        let timeval = libc::timeval {
+            tv_sec:  timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+
+        unsafe {
+            let timeval_len = mem::size_of::<libc::timeval>() as libc::socklen_t;
+            let timeval_ptr = &timeval as *const libc::timeval as *const libc::c_void;
+            if libc::setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_RCVTIMEO, timeval_ptr, timeval_len) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let len = libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0);
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                return if e.kind() == ErrorKind::WouldBlock { Ok(None) } else { Err(e) };
+            }
+
+            Ok(Some(len as usize))
+        }
+    }
+}
+
+impl FromRawFd for Socket {
+    unsafe fn from_raw_fd(fd: RawFd) -> Socket {
+        Socket { fd }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+/// Whether a uevent, a header followed by NUL-separated `KEY=value` pairs, is about a power
+/// supply.
+fn is_power_supply(event: &[u8]) -> bool {
+    event.split(|&byte| byte == 0).any(|field| field == b"SUBSYSTEM=power_supply")
+}
+
+impl Uevent {
+    /// Listens to the kernel's uevent broadcasts on a netlink socket.
+    pub fn open(interval: Duration) -> io::Result<Uevent> {
+        unsafe {
+            let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Closes it on errors
+            let socket = Socket::from_raw_fd(fd);
+
+            // Group 1 has the kernel's own events, rather than udev's rebroadcasts
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = 1;
+
+            let addr_len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            if libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr, addr_len) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Uevent::from_socket(socket, interval))
+        }
+    }
+
+    fn from_socket(socket: Socket, interval: Duration) -> Uevent {
+        Uevent { socket, interval }
+    }
+
+    /// Receives events until one about a power supply, returning whether there was one before the
+    /// timeout.
+    fn next_event(&mut self, timeout: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0; 8192];
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline { return Ok(false) }
+
+            match self.socket.recv(&mut buf, deadline - now) {
+                Ok(Some(len)) => if is_power_supply(&buf[..len]) { return Ok(true) },
+                Ok(None)      => return Ok(false),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e)        => return Err(e),
+            }
+        }
+    }
+}
+
+impl Backend for Uevent {
+    fn wait(&mut self) -> io::Result<()> {
+        if self.next_event(self.interval)? {
+            // Plugging in the AC adapter also changes the batteries, which is waited out
+            while self.next_event(Duration::from_millis(SETTLE_TIME))? {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    /// Listens on one end of a pair of unix sockets, with the other end playing the kernel.
+    fn fake_kernel(interval: Duration) -> (Uevent, UnixDatagram) {
+        let (socket, kernel) = UnixDatagram::pair().unwrap();
+        let socket = unsafe { Socket::from_raw_fd(socket.into_raw_fd()) };
+
+        (Uevent::from_socket(socket, interval), kernel)
+    }
+
+    const USB_EVENT: &[u8] = b"add@/devices/pci0000:00/usb1/1-1\0ACTION=add\0DEVPATH=/devices/pci0000:00/usb1/1-1\0SUBSYSTEM=usb\0";
+    const AC_EVENT:  &[u8] = b"change@/devices/LNXSYSTM:00/ACPI0003:00/power_supply/AC\0ACTION=change\0\
+                               DEVPATH=/devices/LNXSYSTM:00/ACPI0003:00/power_supply/AC\0SUBSYSTEM=power_supply\0\
+                               POWER_SUPPLY_NAME=AC\0POWER_SUPPLY_ONLINE=1\0";
+    const BATTERY_EVENT: &[u8] = b"change@/devices/LNXSYSTM:00/PNP0C0A:00/power_supply/BAT0\0ACTION=change\0\
+                                   DEVPATH=/devices/LNXSYSTM:00/PNP0C0A:00/power_supply/BAT0\0SUBSYSTEM=power_supply\0\
+                                   POWER_SUPPLY_NAME=BAT0\0POWER_SUPPLY_STATUS=Charging\0";
+
+    #[test]
+    fn power_supply_events() {
+        assert!(is_power_supply(AC_EVENT));
+        assert!(!is_power_supply(USB_EVENT));
+        assert!(!is_power_supply(b"change@/power_supply\0SUBSYSTEM=power_supply_extra\0"));
+    }
+
+    #[test]
+    fn ignores_other_events() {
+        let (mut uevent, kernel) = fake_kernel(Duration::from_millis(100));
+
+        kernel.send(USB_EVENT).unwrap();
+        let start = Instant::now();
+        uevent.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn wakes_up_on_events() {
+        let (mut uevent, kernel) = fake_kernel(Duration::from_secs(5));
+
+        // Plugging in the AC adapter also changes the battery, in a burst with unrelated events
+        for event in &[ AC_EVENT, USB_EVENT, BATTERY_EVENT ] {
+            kernel.send(event).unwrap();
+        }
+
+        let start = Instant::now();
+        uevent.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // The whole burst was waited out
+        kernel.send(USB_EVENT).unwrap();
+        assert!(!uevent.next_event(Duration::from_millis(50)).unwrap());
+    }
+}
@@ -1,7 +1,14 @@
-extern crate time;
+extern crate chrono;
+extern crate chrono_tz;
+extern crate libc;
 
+use std::ffi::{CStr, CString};
+use std::mem;
 use std::thread;
+use std::time::Duration;
 
+use self::chrono::{DateTime, Datelike, Offset, Timelike, Utc};
+use self::chrono_tz::Tz;
 use relm_core::Sender;
 use config::Config;
 
@@ -15,14 +22,88 @@ impl Default for Clock {
     }
 }
 
+/// A broken-down time for strftime, with the name of its timezone kept alive for `tm_zone`.
+struct Time {
+    tm:    libc::tm,
+    _zone: Option<CString>,
+}
+
+impl Time {
+    /// `now` in the local timezone, as set by `TZ` or /etc/localtime.
+    fn local(now: &DateTime<Utc>) -> Time {
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        unsafe { libc::localtime_r(&(now.timestamp() as libc::time_t), &mut tm) };
+
+        Time { tm, _zone: None }
+    }
+
+    /// `now` in `timezone`, named by its abbreviation like CET.
+    fn in_timezone(now: &DateTime<Utc>, timezone: Tz) -> Time {
+        let time = now.with_timezone(&timezone);
+        let zone = CString::new(time.offset().to_string()).unwrap_or_default();
+
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        tm.tm_sec    = time.second() as i32;
+        tm.tm_min    = time.minute() as i32;
+        tm.tm_hour   = time.hour() as i32;
+        tm.tm_mday   = time.day() as i32;
+        tm.tm_mon    = time.month0() as i32;
+        tm.tm_year   = time.year() - 1900;
+        tm.tm_wday   = time.weekday().num_days_from_sunday() as i32;
+        tm.tm_yday   = time.ordinal0() as i32;
+        // Unknown (-1) would leave out %z, and `tm_zone` already has the name
+        tm.tm_isdst  = 0;
+        tm.tm_gmtoff = time.offset().fix().local_minus_utc() as libc::c_long;
+        tm.tm_zone   = zone.as_ptr();
+
+        Time { tm, _zone: Some(zone) }
+    }
+
+    /// Formats the time like strftime, with day and month names in the locale's language.
+    fn format(&self, format: &CStr) -> String {
+        let mut buf = [0u8; 256];
+        let len = unsafe { libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), format.as_ptr(), &self.tm) };
+
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+}
+
+/// How long until the next second, or the next minute without seconds.
+fn until_next_tick(now: &DateTime<Utc>, seconds: bool) -> Duration {
+    // Leap seconds count as more nanoseconds
+    let nanos = 1_000_000_000 - now.nanosecond().min(999_999_999);
+
+    if seconds {
+        Duration::new(0, nanos)
+    } else {
+        Duration::new(59 - now.second() as u64, nanos)
+    }
+}
+
 impl Monitor for Clock {
     fn start(self, config: &'static Config, channel: Sender<MonitorMsg>) {
+        // Empty means the one from the environment
+        let locale = CString::new(config.clock.locale.clone().unwrap_or_default()).unwrap_or_default();
+        if unsafe { libc::setlocale(libc::LC_TIME, locale.as_ptr()) }.is_null() {
+            println!("unknown locale {:?}, using the default one", locale);
+        }
+
+        let timezone = config.clock.timezone.as_ref().and_then(|name| match name.parse::<Tz>() {
+            Ok(timezone) => Some(timezone),
+            Err(e)       => { println!("unknown timezone {}, using the local one: {}", name, e); None },
+        });
+
+        let format = CString::new(config.clock.format.replace('\0', "")).unwrap();
+
         thread::spawn(move || {
             loop {
-                let now = ::time::now();
-                let weekday = [ "Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat" ][now.tm_wday as usize];
+                let now = Utc::now();
+                let time = match timezone {
+                    Some(timezone) => Time::in_timezone(&now, timezone),
+                    None           => Time::local(&now),
+                };
 
-                let color = match now.tm_hour {
+                let color = match time.tm.tm_hour {
                      0... 5 => config.get_color("cyan"),
                      6...11 => config.get_color("blue"),
                     12...17 => config.get_color("green"),
@@ -30,16 +111,42 @@ impl Monitor for Clock {
                     _       => unreachable!()
                 };
 
-                let important = now.tm_nsec % 2 == 0;
-                let text = format!("{} {} {:02}:{:02}", weekday, now.tm_mday, now.tm_hour, now.tm_min);
-                println!("{} / important: {}", text, important);
-                channel.send(MonitorMsg::SetText(text));
+                channel.send(MonitorMsg::SetText(time.format(&format)));
                 channel.send(MonitorMsg::SetColor(color));
                 channel.send(MonitorMsg::SetRelevance(Relevance::Urgent));
 
-                let sleep_time = ::std::time::Duration::new(59 - now.tm_sec as u64 % 60, 1000000000 - now.tm_nsec as u32);
-                thread::sleep(sleep_time);
+                thread::sleep(until_next_tick(&Utc::now(), config.clock.seconds));
             }
         });
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::chrono::TimeZone;
+    use super::*;
+
+    #[test]
+    fn formats_in_timezone() {
+        let format = CString::new("%a %-d %b %Y %H:%M:%S %Z %z").unwrap();
+        let berlin = "Europe/Berlin".parse::<Tz>().unwrap();
+
+        // Right before and after the switch to summer time, in the C locale tests run in
+        let before = Utc.ymd(2018, 3, 25).and_hms(0, 59, 59);
+        let after  = Utc.ymd(2018, 3, 25).and_hms(1, 0, 0);
+        assert_eq!(Time::in_timezone(&before, berlin).format(&format), "Sun 25 Mar 2018 01:59:59 CET +0100");
+        assert_eq!(Time::in_timezone(&after, berlin).format(&format), "Sun 25 Mar 2018 03:00:00 CEST +0200");
+
+        let tokyo = "Asia/Tokyo".parse::<Tz>().unwrap();
+        let format = CString::new("%A %j %I%p").unwrap();
+        assert_eq!(Time::in_timezone(&Utc.ymd(2018, 12, 31).and_hms(20, 0, 0), tokyo).format(&format), "Tuesday 001 05AM");
+    }
+
+    #[test]
+    fn ticks() {
+        let now = Utc.ymd(2018, 3, 25).and_hms_milli(12, 0, 45, 250);
+
+        assert_eq!(until_next_tick(&now, true), Duration::from_millis(750));
+        assert_eq!(until_next_tick(&now, false), Duration::from_millis(14750));
+    }
+}
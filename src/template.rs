@@ -0,0 +1,179 @@
+use std::str::FromStr;
+use ::serde::{self, Deserialize, Deserializer};
+
+/// Text with `{name}` placeholders, filled in when rendering. Text in `[brackets]` is only shown if
+/// all placeholders in it have a value, like `{title}[ ({album})]`. Sections can have alternatives
+/// separated by `|`, the first one with all its values is shown. A backslash escapes the next
+/// character.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Template(Vec<Part>);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(String),
+    /// Alternatives, of which the first complete one is shown.
+    Section(Vec<Vec<Part>>),
+}
+
+impl Template {
+    /// Fills in the placeholders with the values `lookup` finds for their names. Missing and empty
+    /// values collapse the sections they're in, or are left out outside of them.
+    pub fn render<F>(&self, lookup: F) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut out = String::new();
+        render(&self.0, &lookup, &mut out);
+        out
+    }
+}
+
+/// Appends the parts to `out`, returning whether all placeholders had a value.
+fn render<F>(parts: &[Part], lookup: &F, out: &mut String) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut complete = true;
+
+    for part in parts {
+        match *part {
+            Part::Text(ref text) => out.push_str(text),
+            Part::Placeholder(ref name) => match lookup(name) {
+                Some(ref value) if !value.is_empty() => out.push_str(value),
+                _                                    => complete = false,
+            },
+            Part::Section(ref alternatives) => {
+                for parts in alternatives {
+                    let mut section = String::new();
+                    if render(parts, lookup, &mut section) {
+                        out.push_str(&section);
+                        break;
+                    }
+                }
+            },
+        }
+    }
+
+    complete
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Finished parts and alternatives of the enclosing sections, innermost last
+        let mut stack: Vec<(Vec<Part>, Vec<Vec<Part>>)> = vec![];
+        let mut alternatives = vec![];
+        let mut parts = vec![];
+        let mut text  = String::new();
+
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => text.push(chars.next().ok_or("template ends in a backslash")?),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c)   => name.push(c),
+                            None      => return Err(format!("unclosed placeholder {{{}", name)),
+                        }
+                    }
+                    if name.is_empty() { return Err("empty placeholder {}".to_string()) }
+
+                    if !text.is_empty() { parts.push(Part::Text(text.split_off(0))) }
+                    parts.push(Part::Placeholder(name));
+                },
+                '}' => return Err("unopened }".to_string()),
+                '[' => {
+                    if !text.is_empty() { parts.push(Part::Text(text.split_off(0))) }
+                    stack.push((parts.split_off(0), alternatives.split_off(0)));
+                },
+                '|' if !stack.is_empty() => {
+                    if !text.is_empty() { parts.push(Part::Text(text.split_off(0))) }
+                    alternatives.push(parts.split_off(0));
+                },
+                ']' => {
+                    if !text.is_empty() { parts.push(Part::Text(text.split_off(0))) }
+                    let (mut outer, outer_alternatives) = stack.pop().ok_or("unopened ]")?;
+                    alternatives.push(parts);
+                    outer.push(Part::Section(alternatives));
+                    parts = outer;
+                    alternatives = outer_alternatives;
+                },
+                c => text.push(c),
+            }
+        }
+
+        if !stack.is_empty() { return Err("unclosed [".to_string()) }
+        if !text.is_empty() { parts.push(Part::Text(text)) }
+
+        Ok(Template(parts))
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(de: D) -> Result<Template, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let s = String::deserialize(de)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn render(template: &str, values: &[(&str, &str)]) -> String {
+        let values = values.iter().cloned().collect::<HashMap<_, _>>();
+        template.parse::<Template>().unwrap().render(|name| values.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("{artist} - {title}", &[ ("artist", "Artist"), ("title", "Song") ]), "Artist - Song");
+        assert_eq!(render("{artist} - {title}", &[ ("title", "Song") ]), " - Song");
+        assert_eq!(render("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn sections() {
+        let template = "[{artist} - ]{title}[ ({album}[, {date}])]";
+
+        assert_eq!(render(template, &[ ("title", "Song") ]), "Song");
+        assert_eq!(render(template, &[ ("artist", ""), ("title", "Song"), ("album", "Album") ]), "Song (Album)");
+        assert_eq!(render(template, &[ ("artist", "Artist"), ("title", "Song"), ("album", "Album"), ("date", "1999") ]),
+                   "Artist - Song (Album, 1999)");
+
+        // The inner section collapsing doesn't collapse the outer one, but a missing tag does
+        assert_eq!(render("[{album}[ {date}]]", &[ ("album", "Album") ]), "Album");
+        assert_eq!(render("[{album}[ {date}]]", &[ ("date", "1999") ]), "");
+    }
+
+    #[test]
+    fn alternatives() {
+        let template = "[{artist} - {title}|{title}|{file}] | [{date}|unknown]";
+
+        assert_eq!(render(template, &[ ("artist", "Artist"), ("title", "Song"), ("file", "song.ogg") ]), "Artist - Song | unknown");
+        assert_eq!(render(template, &[ ("title", "Song"), ("file", "song.ogg"), ("date", "1999") ]), "Song | 1999");
+        assert_eq!(render(template, &[ ("artist", "Artist"), ("file", "song.ogg") ]), "song.ogg | unknown");
+        assert_eq!(render("[{title}|{file}]", &[]), "");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render(r"\[{title}\|\] \{\\", &[ ("title", "Song") ]), r"[Song|] {\");
+    }
+
+    #[test]
+    fn errors() {
+        for template in &[ "{title", "{}", "title}", "[{title}", "{title}]", "\\" ] {
+            assert!(template.parse::<Template>().is_err(), "{} should not parse", template);
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod workspace;
+pub mod scratchpad;
+pub mod window_title;
+pub mod mode;
+pub mod music;
 pub mod monitor_bar;
 // pub mod status; pub use self::status::*;
-// pub mod music; pub use self::music::*;
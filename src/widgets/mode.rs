@@ -0,0 +1,194 @@
+extern crate cairo;
+extern crate gtk;
+extern crate pango;
+extern crate pangocairo;
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+
+use gtk::prelude::*;
+use self::pango::prelude::LayoutExt;
+use relm::{Channel, Relm, Update, Widget};
+
+use ::color::Color;
+use ::config::Config;
+use ::ipc::{self, Connection, Event};
+
+pub struct ModeModel {
+    config:  &'static Config,
+    channel: Channel<ModeMsg>,
+    mode:    Option<Mode>,
+}
+
+/// Shows the active binding mode, and takes up no space while in the default one.
+pub struct ModeWidget {
+    model:  Rc<RefCell<ModeModel>>,
+    widget: gtk::DrawingArea,
+}
+
+#[derive(Debug, Msg)]
+pub enum ModeMsg {
+    /// A binding mode was entered, or `None` when returning to the default mode.
+    Change(Option<Mode>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mode {
+    name:   String,
+    markup: bool,
+}
+
+impl ModeWidget {
+    fn render(model: &ModeModel, widget: &gtk::DrawingArea, cx: &cairo::Context) {
+        let height = widget.get_allocated_height() as f64;
+
+        let mode = match model.mode {
+            Some(ref mode) => mode,
+            None           => return,
+        };
+
+        let font = pango::FontDescription::from_string(&format!("Droid Sans Mono {}", model.config.dpi_scale(10)));
+        let layout = pangocairo::functions::create_layout(cx).unwrap();
+        if mode.markup {
+            layout.set_markup(&mode.name);
+        } else {
+            layout.set_text(&mode.name);
+        }
+        layout.set_font_description(Some(&font));
+
+        let padding = model.config.dpi_scale(8) as f64;
+        let margin  = model.config.dpi_scale(4) as f64;
+        let (text_width, text_height) = layout.get_pixel_size();
+        let (text_width, text_height) = (text_width as f64, text_height as f64);
+
+        let Color(r, g, b, a) = model.config.mode.background;
+        cx.set_source_rgba(r, g, b, a);
+        cx.rectangle(margin, margin, text_width + padding * 2.0, height - margin * 2.0);
+        cx.fill();
+
+        let Color(r, g, b, a) = model.config.mode.foreground;
+        cx.set_source_rgba(r, g, b, a);
+        cx.move_to(margin + padding, height / 2.0 - text_height / 2.0);
+        pangocairo::functions::show_layout(cx, &layout);
+
+        widget.set_size_request((text_width + padding * 2.0 + margin * 2.0) as i32, -1);
+    }
+}
+
+/// Emits the binding mode every time it changes, until the connection to the window manager is
+/// lost.
+fn watch<F>(path: &Path, mut emit: F) -> io::Result<()>
+where
+    F: FnMut(Option<Mode>),
+{
+    let mut listener = Connection::connect_to(path)?.subscribe(&[ "mode" ])?;
+
+    loop {
+        if let Event::Mode(e) = listener.next_event()? {
+            if e.change == "default" {
+                emit(None);
+            } else {
+                emit(Some(Mode {
+                    name:   e.change,
+                    markup: e.pango_markup,
+                }));
+            }
+        }
+    }
+}
+
+impl Update for ModeWidget {
+    type Model = ModeModel;
+    type ModelParam = &'static Config;
+    type Msg = ModeMsg;
+
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        let stream = relm.stream().clone();
+
+        let (channel, sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
+
+        thread::spawn(move || {
+            ipc::supervise(
+                |path| watch(path, |mode| {
+                    sx.send(ModeMsg::Change(mode));
+                }),
+                || {
+                    sx.send(ModeMsg::Change(None));
+                },
+            );
+        });
+
+        ModeModel {
+            config,
+            channel,
+            mode: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        use self::ModeMsg::*;
+        match msg {
+            Change(mode) => {
+                self.widget.set_visible(mode.is_some());
+                self.model.borrow_mut().mode = mode;
+            },
+        }
+        self.widget.queue_draw();
+    }
+}
+
+impl Widget for ModeWidget {
+    type Root = gtk::DrawingArea;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(_relm: &Relm<Self>, model: Self::Model) -> Self {
+        let widget = gtk::DrawingArea::new();
+        let model = Rc::new(RefCell::new(model));
+
+        // Hidden until a mode is entered, even when the bar calls show_all
+        widget.set_no_show_all(true);
+
+        widget.connect_draw(clone!(model => move |widget, cx| {
+            ModeWidget::render(&model.borrow(), widget, cx);
+            Inhibit(false)
+        }));
+
+        ModeWidget {
+            model,
+            widget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::ipc::{self, fake::FakeServer};
+    use super::*;
+
+    #[test]
+    fn follows_modes() {
+        let server = FakeServer::start(vec![], vec![
+            (ipc::EVENT_MODE, r#"{"change":"resize","pango_markup":false}"#),
+            (ipc::EVENT_MODE, r#"{"change":"<b>launch</b>","pango_markup":true}"#),
+            (ipc::EVENT_MODE, r#"{"change":"default","pango_markup":false}"#),
+        ]);
+
+        let mut updates = vec![];
+        let res = watch(&server.path, |mode| updates.push(mode));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        assert_eq!(updates, vec![
+            Some(Mode { name: "resize".to_string(),        markup: false }),
+            Some(Mode { name: "<b>launch</b>".to_string(), markup: true }),
+            None,
+        ]);
+    }
+}
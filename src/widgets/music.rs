@@ -1,175 +1,689 @@
 extern crate cairo;
 extern crate gdk;
-extern crate gdk_sys;
+extern crate gdk_pixbuf;
+extern crate glib;
 extern crate gtk;
-extern crate mpd;
 extern crate pango;
 extern crate pangocairo;
+extern crate time;
 
-use config::{Color, Config};
-use gtk::prelude::*;
-use self::mpd::Idle;
 use std::cell::{Cell, RefCell};
-use std::default::Default;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::mpsc;
 use std::thread;
 
-pub struct MusicComponent {
-    pub widget: gtk::DrawingArea,
-    text: String,
-    icon: String,
-    color: Color,
+use gdk::prelude::ContextExt;
+use gtk::prelude::*;
+use self::gdk_pixbuf::{InterpType, Pixbuf, PixbufExt, PixbufLoader, PixbufLoaderExt};
+use self::pango::prelude::LayoutExt;
+use relm::{Channel, Relm, Update, Widget};
+
+use ::color::Color;
+use ::config::{Config, LyricsDisplay, TextOverflow};
+use ::media::{self, art, Command, Event, PlayOption, PlayState, Remote, Song};
+use ::media::lyrics::{self, Lyrics};
+
+pub struct MusicModel {
+    config:  &'static Config,
+    channel: Channel<MusicMsg>,
+    remote:  Option<Remote>,
+    song:    Option<Song>,
+    /// When the volume last changed, it's shown next to the song for a while after.
+    volume_changed: f64,
+    /// Whether the timer advancing the elapsed time is running.
+    ticking: bool,
+    /// Whether the text is wider than the maximum, as of the last draw.
+    overflowing: Cell<bool>,
+    /// Whether the marquee timer is running.
+    scrolling: bool,
+    /// When the current text started scrolling.
+    scroll_start: f64,
+    /// Album art of the albums shown last, scaled to the bar.
+    art: ArtCache,
+    /// Album keys the art is being fetched for.
+    art_fetching: HashSet<String>,
+    /// Album keys and song URIs to fetch the art for on the fetching thread.
+    art_requests: mpsc::Sender<(String, String)>,
+    art_channel:  Channel<MusicMsg>,
+    queue:   media::Queue,
+    lyrics:  Option<Lyrics>,
+    options: media::Options,
 }
 
-enum MpdMessage {
-    Text(String),
-    Color(Color),
+/// Shows the song MPD or an MPRIS player is playing, and controls playback with the mouse.
+pub struct MusicWidget {
+    model:     Rc<RefCell<MusicModel>>,
+    widget:    gtk::DrawingArea,
+    popover:   gtk::Popover,
+    /// The lines of the lyrics around the one being sung, at the top of the popup.
+    lyrics:    gtk::Label,
+    songs:     gtk::ListBox,
+    playlists: gtk::ListBox,
+    /// The toggles and outputs, hidden until MPD reports them.
+    options:   gtk::Box,
+    toggles:   Vec<(PlayOption, gtk::ToggleButton)>,
+    outputs:   gtk::ListBox,
 }
 
-impl MusicComponent {
-    pub fn new(config: &'static Config) -> Rc<RefCell<Self>> {
-        let widget = gtk::DrawingArea::new();
-        widget.set_size_request(10, -1);
-        widget.set_vexpand(true);
-
-        let music_component = Rc::new(RefCell::new(MusicComponent {
-            widget: widget,
-            text: String::new(),
-            icon: String::new(),
-            color: Color::default(),
-        }));
+#[derive(Debug, Msg)]
+pub enum MusicMsg {
+    /// A connection to the player was made, commands go through this remote until the next one.
+    Connected(Remote),
+    /// The song or its state changed, or `None` when disconnected.
+    Update(Option<Song>),
+    /// The encoded album art for an album key was fetched, or failed to be.
+    Art(String, io::Result<Option<Vec<u8>>>),
+    Queue(media::Queue),
+    Click(u32, gdk::ModifierType),
+    Scroll(gdk::ScrollDirection),
+    /// A song in the popup was clicked, by its row.
+    PlaySong(i32),
+    /// A playlist in the popup was clicked, by its row.
+    LoadPlaylist(i32),
+    Options(media::Options),
+    SetOption(PlayOption, bool),
+    /// An output in the popup was clicked, by its row.
+    ToggleOutput(i32),
+}
 
-        {
-            let ref mut widget = music_component.borrow_mut().widget;
+/// How far to scroll text that's `overflow` pixels too wide, `t` seconds after it started. It
+/// waits at one end, scrolls to the other at `speed` pixels per second, waits there and returns.
+fn marquee_offset(overflow: f64, speed: f64, t: f64) -> f64 {
+    let pause  = 1.5;
+    let travel = overflow / speed;
+
+    let t = t % (2.0 * (pause + travel));
+    if t < pause                     { 0.0 }
+    else if t < pause + travel       { (t - pause) * speed }
+    else if t < pause * 2.0 + travel { overflow }
+    else                             { overflow - (t - pause * 2.0 - travel) * speed }
+}
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Fills in the format for the song's state with its tags and times.
+fn song_text(config: &Config, song: &Song) -> String {
+    let template = match song.state {
+        PlayState::Playing => &config.music.format.playing,
+        PlayState::Paused  => &config.music.format.paused,
+        PlayState::Stopped => &config.music.format.stopped,
+    };
+
+    template.render(|name| match name {
+        "elapsed"  => Some(format_time(song.position())),
+        "duration" => if song.duration > 0.0 { Some(format_time(song.duration)) } else { None },
+        _          => song.tags.get(name).cloned(),
+    })
+}
+
+/// How many albums' art is kept around.
+const ART_CACHED: usize = 16;
+
+/// Album art by `art::album_key`, `None` for albums without any. Only the most recently used
+/// albums are kept.
+struct ArtCache {
+    /// The least recently used first.
+    entries: VecDeque<(String, Option<Pixbuf>)>,
+}
+
+impl ArtCache {
+    fn new() -> ArtCache {
+        ArtCache { entries: VecDeque::with_capacity(ART_CACHED + 1) }
+    }
 
-            // widget.connect_button_release_event(clone!(music_component => move |widget, event| {
-            //     music_component.borrow().button_release(widget, event)
-            // }));
+    fn get(&self, key: &str) -> Option<&Pixbuf> {
+        self.entries.iter().find(|entry| entry.0 == key).and_then(|entry| entry.1.as_ref())
+    }
 
-            widget.connect_draw(clone!(music_component => move |widget, cx| {
-                music_component.borrow().draw(widget, cx)
-            }));
+    /// Marks an album as just used, returning whether it's cached.
+    fn touch(&mut self, key: &str) -> bool {
+        match self.entries.iter().position(|entry| entry.0 == key) {
+            Some(i) => {
+                let entry = self.entries.remove(i).unwrap();
+                self.entries.push_back(entry);
+                true
+            },
+            None => false,
         }
+    }
 
-        let (sx, rx) = mpsc::channel::<Vec<MpdMessage>>();
+    fn insert(&mut self, key: String, art: Option<Pixbuf>) {
+        self.entries.retain(|entry| entry.0 != key);
+        self.entries.push_back((key, art));
 
-        thread::spawn(move || {
-            let mut conn = mpd::Client::connect((config.mpd.host.as_str(), config.mpd.port)).unwrap();
-            loop {
-                use self::mpd::State::*;
-                match conn.status().unwrap().state {
-                    Stop => {
-                        let _ = sx.send(vec![MpdMessage::Text(String::new())]);
-                    },
-                    state => {
-                        let song = conn.currentsong().unwrap().unwrap();
-                        let text;
-
-                        if let Some(artist) = song.tags.get("Artist") {
-                            text = format!("{} - {}", artist, song.title.unwrap_or("<no title>".to_string()));
-                        } else {
-                            text = format!("{}", song.file);
-                        }
-
-                        let color = match state {
-                            Play  => config.get_color("green"),
-                            Pause => config.get_color("yellow"),
-                            _     => unreachable!()
-                        };
-
-                        let _ = sx.send(vec![
-                            MpdMessage::Text(text),
-                            MpdMessage::Color(color)
-                        ]);
-                    },
-                }
-
-                let _ = conn.wait(&[mpd::Subsystem::Player]);
+        if self.entries.len() > ART_CACHED {
+            self.entries.pop_front();
+        }
+    }
+}
+
+/// The size of the square album art is shown in, as high as the bar minus some padding.
+fn art_size(config: &Config, height: i32) -> i32 {
+    height - config.dpi_scale(2) * 2
+}
+
+/// Decodes album art and scales it to fit a square of `size`, so it doesn't have to be scaled on
+/// every draw and full size images aren't kept around.
+fn decode_art(data: Vec<u8>, size: i32) -> Option<Pixbuf> {
+    let loader = PixbufLoader::new();
+    if let Err(e) = loader.write(&data).and_then(|_| loader.close()) {
+        println!("failed to decode album art: {}", e);
+        return None;
+    }
+
+    let pixbuf = loader.get_pixbuf()?;
+    let (width, height) = (pixbuf.get_width() as f64, pixbuf.get_height() as f64);
+    let scale = (size as f64 / width).min(size as f64 / height);
+
+    pixbuf.scale_simple(
+        ((width * scale).round() as i32).max(1),
+        ((height * scale).round() as i32).max(1),
+        InterpType::Bilinear,
+    )
+}
+
+/// Replaces the rows of a list in the popup.
+fn fill<I: Iterator<Item = String>>(list: &gtk::ListBox, rows: I) {
+    for child in list.get_children() {
+        list.remove(&child);
+    }
+    for row in rows {
+        let label = gtk::Label::new(Some(row.as_str()));
+        label.set_xalign(0.0);
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_max_width_chars(60);
+        list.insert(&label, -1);
+    }
+    list.show_all();
+}
+
+/// How long to show the volume after it changed, in seconds.
+const VOLUME_SHOWN: f64 = 2.0;
+
+impl MusicWidget {
+    fn render(model: &MusicModel, widget: &gtk::DrawingArea, cx: &cairo::Context) {
+        let height = widget.get_allocated_height() as f64;
+
+        let song = model.song.as_ref();
+        let mut text = song.map(|song| song_text(model.config, song)).unwrap_or_default();
+
+        let song = match song {
+            Some(song) if !text.is_empty() => song,
+            _                              => {
+                model.overflowing.set(false);
+                widget.set_size_request(0, -1);
+                return;
+            },
+        };
+
+        let font = pango::FontDescription::from_string(&format!("Droid Sans Mono {}", model.config.dpi_scale(10)));
+        let position = song.position();
+
+        if model.config.music.lyrics == LyricsDisplay::Bar {
+            if let Some(line) = model.lyrics.as_ref().and_then(|lyrics| lyrics.line_at(position)) {
+                text = format!("{} · {}", text, line);
             }
+        }
+        if time::precise_time_s() - model.volume_changed < VOLUME_SHOWN {
+            text = format!("{} · {}%", text, song.volume);
+        }
+
+        let layout = pangocairo::functions::create_layout(cx).unwrap();
+        layout.set_text(&text);
+        layout.set_font_description(Some(&font));
+
+        let margin    = model.config.dpi_scale(12) as f64;
+        let max_width = model.config.dpi_scale(model.config.music.max_width);
+
+        // Album art in a square as high as the bar, already scaled to it when it was decoded
+        let art = art::album_key(song).and_then(|key| model.art.get(&key));
+        let art_width = match art {
+            Some(pixbuf) => {
+                let padding = (height - art_size(model.config, height as i32) as f64) / 2.0;
+
+                cx.save();
+                cx.set_source_pixbuf(pixbuf, margin, padding);
+                cx.paint();
+                cx.restore();
+
+                pixbuf.get_width() as f64 + margin / 2.0
+            },
+            None => 0.0,
+        };
+        let left = margin + art_width;
+
+        let (mut text_width, text_height) = layout.get_pixel_size();
+        let overflow = text_width - max_width;
+        model.overflowing.set(overflow > 0);
+
+        let Color(r, g, b, a) = model.config.get_color(match song.state {
+            PlayState::Playing => "green",
+            PlayState::Paused  => "yellow",
+            PlayState::Stopped => "white",
         });
+        cx.set_source_rgba(r, g, b, a);
+
+        if overflow > 0 && model.config.music.overflow == TextOverflow::Scroll {
+            let speed  = 30.0 * model.config.dpi;
+            let offset = marquee_offset(overflow as f64, speed, time::precise_time_s() - model.scroll_start);
+
+            cx.save();
+            cx.rectangle(left, 0.0, max_width as f64, height);
+            cx.clip();
+            cx.move_to(left - offset.round(), height / 2.0 - text_height as f64 / 2.0);
+            pangocairo::functions::show_layout(cx, &layout);
+            cx.restore();
+        } else {
+            layout.set_width(max_width * pango::SCALE);
+            layout.set_ellipsize(pango::EllipsizeMode::End);
+
+            cx.move_to(left, height / 2.0 - text_height as f64 / 2.0);
+            pangocairo::functions::show_layout(cx, &layout);
+        }
 
-        gtk::timeout_add(50, clone!(music_component => move || {
-            if let Ok(changes) = rx.try_recv() {
-                let mut comp = music_component.borrow_mut();
+        text_width = text_width.min(max_width);
 
-                for change in changes {
-                    match change {
-                        MpdMessage::Text(text)   => comp.text  = text,
-                        MpdMessage::Color(color) => comp.color = color,
-                    }
-                }
+        // A thin progress line under the text, over a dimmed one for the rest of the song
+        if song.duration > 0.0 && song.state != PlayState::Stopped {
+            let line_width = (1.0 * model.config.dpi).floor().max(1.0);
+            let y = (height / 2.0 + text_height as f64 / 2.0 + line_width).floor() + line_width / 2.0;
+            let progress = text_width as f64 * position / song.duration;
 
-                comp.widget.queue_draw();
+            cx.set_line_width(line_width);
+            cx.move_to(left, y);
+            cx.line_to(left + progress, y);
+            cx.stroke();
+
+            cx.set_source_rgba(r, g, b, a * 0.3);
+            cx.move_to(left + progress, y);
+            cx.line_to(left + text_width as f64, y);
+            cx.stroke();
+        }
+
+        widget.set_size_request(text_width + (2.0 * margin + art_width) as i32, -1);
+    }
+
+    fn update_song(&self, song: Option<Song>) {
+        let mut model = self.model.borrow_mut();
+
+        let volume_changed = match (&model.song, &song) {
+            (Some(old), Some(new)) => old.volume != new.volume,
+            _                      => false,
+        };
+
+        if volume_changed {
+            model.volume_changed = time::precise_time_s();
+
+            // Redraw once it's time to hide the volume again
+            let widget = self.widget.clone();
+            gtk::timeout_add((VOLUME_SHOWN * 1000.0) as u32, move || {
+                widget.queue_draw();
+                gtk::Continue(false)
+            });
+        }
+
+        if model.song.as_ref().map(|song| &song.tags) != song.as_ref().map(|song| &song.tags) {
+            model.scroll_start = time::precise_time_s();
+        }
+
+        // Each album's art is fetched once unless that fails, the fetching thread answers with an
+        // Art message
+        if let Some(key) = song.as_ref().filter(|_| model.config.music.art).and_then(art::album_key) {
+            if !model.art.touch(&key) && !model.art_fetching.contains(&key) {
+                let uri = song.as_ref().unwrap().uri.clone();
+                let _ = model.art_requests.send((key.clone(), uri));
+                model.art_fetching.insert(key);
             }
+        }
+
+        // Lyrics files are small enough to read right away
+        let config = model.config;
+        let song_changed = model.song.as_ref().map(|song| &song.uri) != song.as_ref().map(|song| &song.uri);
+        if song_changed && config.music.lyrics != LyricsDisplay::None {
+            let directories = (config.music.directory.as_ref(), config.music.lyrics_directory.as_ref());
+            model.lyrics = song.as_ref().and_then(|song| {
+                lyrics::find(directories.0.map(PathBuf::as_path), directories.1.map(PathBuf::as_path), song)
+            });
+        }
 
-            Continue(true)
+        model.song = song;
+        MusicWidget::show_lyrics(&model, &self.lyrics);
+        drop(model);
+
+        self.tick();
+    }
+
+    /// Caches fetched album art, scaled to the bar. Art that failed to be fetched is tried again the
+    /// next time the song updates.
+    fn update_art(&self, key: String, data: io::Result<Option<Vec<u8>>>) {
+        let height = self.widget.get_allocated_height();
+
+        let mut model = self.model.borrow_mut();
+        model.art_fetching.remove(&key);
+
+        if let Ok(data) = data {
+            let size = art_size(model.config, height);
+            model.art.insert(key, data.and_then(|data| decode_art(data, size)));
+        }
+    }
+
+    /// Shows the line of the lyrics being sung in the popup, between the one before and the ones
+    /// after it.
+    fn show_lyrics(model: &MusicModel, label: &gtk::Label) {
+        let lines = match (&model.lyrics, &model.song) {
+            (Some(lyrics), Some(song)) if model.config.music.lyrics == LyricsDisplay::Popup => {
+                lyrics.lines_around(song.position(), 1, 2)
+            },
+            _ => {
+                label.hide();
+                return;
+            },
+        };
+
+        let markup = lines.iter().enumerate()
+            .map(|(i, line)| {
+                let line = glib::markup_escape_text(line);
+                if i == 1 { format!("<b>{}</b>", line) } else { format!("{}", line) }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        label.set_markup(&markup);
+        label.show();
+    }
+
+    /// Fills the popup's lists, with the current song marked.
+    fn update_queue(&self, queue: media::Queue) {
+        fill(&self.songs, queue.songs.iter().map(|&(pos, ref text)| {
+            let marker = if Some(pos) == queue.current { "▶" } else { " " };
+            format!("{} {:>3}  {}", marker, pos + 1, text)
+        }));
+        fill(&self.playlists, queue.playlists.iter().cloned());
+
+        self.model.borrow_mut().queue = queue;
+    }
+
+    /// Sets the toggles and fills the outputs, with the enabled ones checked. The model is updated
+    /// first, so the toggles being set don't send the same options back to MPD.
+    fn update_options(&self, options: media::Options) {
+        self.model.borrow_mut().options = options.clone();
+
+        for &(option, ref button) in &self.toggles {
+            button.set_active(options.get(option));
+        }
+        fill(&self.outputs, options.outputs.iter().map(|&(_, ref name, enabled)| {
+            format!("{} {}", if enabled { "✓" } else { " " }, name)
         }));
 
-        music_component
+        self.options.set_no_show_all(false);
+        self.options.show_all();
+    }
+
+    /// Redraws every second while playing to advance the elapsed time, or four times a second to
+    /// keep up with lyrics, unless that's already happening. New statuses resync it, and the timer
+    /// stops once MPD pauses or stops.
+    fn tick(&self) {
+        {
+            let mut model = self.model.borrow_mut();
+            if model.ticking || !model.song.as_ref().map_or(false, |song| song.state == PlayState::Playing) {
+                return;
+            }
+            model.ticking = true;
+        }
+
+        let interval = if self.model.borrow().config.music.lyrics == LyricsDisplay::None { 1000 } else { 250 };
+
+        let (model, widget, lyrics) = (self.model.clone(), self.widget.clone(), self.lyrics.clone());
+        gtk::timeout_add(interval, move || {
+            let mut model = model.borrow_mut();
+            model.ticking = model.song.as_ref().map_or(false, |song| song.state == PlayState::Playing);
+
+            MusicWidget::show_lyrics(&model, &lyrics);
+            widget.queue_draw();
+            gtk::Continue(model.ticking)
+        });
+    }
+
+    /// Redraws often while the text overflows and should scroll, unless that's already
+    /// happening. The timer stops once the text fits.
+    fn scroll(model: &Rc<RefCell<MusicModel>>, widget: &gtk::DrawingArea) {
+        {
+            let mut model = model.borrow_mut();
+            if model.config.music.overflow != TextOverflow::Scroll || model.scrolling || !model.overflowing.get() {
+                return;
+            }
+            model.scrolling = true;
+        }
+
+        let (model, widget) = (model.clone(), widget.clone());
+        gtk::timeout_add(40, move || {
+            let mut model = model.borrow_mut();
+            model.scrolling = model.overflowing.get();
+
+            widget.queue_draw();
+            gtk::Continue(model.scrolling)
+        });
     }
+}
 
-    fn draw(&self, widget: &gtk::DrawingArea, context: &cairo::Context) -> gtk::Inhibit {
-        let width    = widget.get_allocated_width()  as f64;
-        let height   = widget.get_allocated_height() as f64;
-        let margin   = 12.0; // around widget
-        let padding  = 10.0; // between icon and text
+impl Update for MusicWidget {
+    type Model = MusicModel;
+    type ModelParam = &'static Config;
+    type Msg = MusicMsg;
 
-        let icon = &self.icon;
-        let text = &self.text;
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        let stream = relm.stream().clone();
 
-        let mut used_width = margin;
+        let (channel, sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
 
-        let layout = pangocairo::create_layout(context);
+        // Album art is fetched on a thread of its own, one album at a time
+        let stream = relm.stream().clone();
+        let (art_channel, art_sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
 
-        if icon != "" {
-            let font = pango::FontDescription::from_string("Material Icons 12");
-            layout.set_text(icon, icon.len() as i32);
-            layout.set_font_description(Some(&font));
+        let (art_requests, requests) = mpsc::channel::<(String, String)>();
+        thread::spawn(move || {
+            for (key, uri) in requests {
+                art_sx.send(MusicMsg::Art(key, art::fetch(config, &uri)));
+            }
+        });
+
+        media::spawn(config, move |event| match event {
+            Event::Connected(remote) => sx.send(MusicMsg::Connected(remote)),
+            Event::Song(song)        => sx.send(MusicMsg::Update(song)),
+            Event::Queue(queue)      => sx.send(MusicMsg::Queue(queue)),
+            Event::Options(options)  => sx.send(MusicMsg::Options(options)),
+        });
 
-            let extents = layout.get_extents().0;
-            let (icon_x, icon_y) = (extents.x as f64 / pango::SCALE as f64,
-                                    extents.y as f64 / pango::SCALE as f64);
-            let (icon_width, icon_height) = (extents.width  as f64 / pango::SCALE as f64,
-                                             extents.height as f64 / pango::SCALE as f64);
+        MusicModel {
+            config,
+            channel,
+            remote: None,
+            song:   None,
+            volume_changed: 0.0,
+            ticking: false,
+            overflowing:  Cell::new(false),
+            scrolling:    false,
+            scroll_start: 0.0,
+            art:          ArtCache::new(),
+            art_fetching: HashSet::new(),
+            art_requests,
+            art_channel,
+            queue:   media::Queue::default(),
+            lyrics:  None,
+            options: media::Options::default(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        use self::MusicMsg::*;
+
+        let command = match msg {
+            Connected(remote) => { self.model.borrow_mut().remote = Some(remote); None },
+            Update(song)      => { self.update_song(song); None },
+            Art(key, data)    => { self.update_art(key, data); None },
+            Queue(queue)      => { self.update_queue(queue); None },
+            Click(1, state) if state.contains(gdk::ModifierType::CONTROL_MASK) => { self.popover.show_all(); None },
+            Click(1, _)       => Some(Command::TogglePause),
+            Click(2, _)       => Some(Command::Previous),
+            Click(3, _)       => Some(Command::Next),
+            Click(_, _)       => None,
+            PlaySong(row) => {
+                self.popover.hide();
+                self.model.borrow().queue.songs.get(row as usize).map(|&(pos, _)| Command::Play(pos))
+            },
+            LoadPlaylist(row) => {
+                self.popover.hide();
+                self.model.borrow().queue.playlists.get(row as usize).cloned().map(Command::LoadPlaylist)
+            },
+            Options(options) => { self.update_options(options); None },
+            SetOption(option, on) => {
+                if self.model.borrow().options.get(option) == on { None } else { Some(Command::SetOption(option, on)) }
+            },
+            ToggleOutput(row) => {
+                self.model.borrow().options.outputs.get(row as usize).map(|&(id, _, enabled)| Command::SetOutput(id, !enabled))
+            },
+            Scroll(gdk::ScrollDirection::Up)   => Some(Command::Volume(5)),
+            Scroll(gdk::ScrollDirection::Down) => Some(Command::Volume(-5)),
+            Scroll(_)                          => None,
+        };
+
+        if let Some(command) = command {
+            if let Some(ref mut remote) = self.model.borrow_mut().remote {
+                remote.send(command);
+            }
+        }
 
-            let x = -icon_x as f64 + margin;
-            let y = -icon_y as f64 + height / 2.0 - icon_height / 2.0;
+        self.widget.queue_draw();
+    }
+}
 
-            let Color(r, g, b, a) = self.color;
-            context.set_source_rgba(r, g, b, a);
+impl Widget for MusicWidget {
+    type Root = gtk::DrawingArea;
 
-            context.move_to(x, y);
-            pangocairo::show_layout(&context, &layout);
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
 
-            used_width += icon_width + padding;
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let widget = gtk::DrawingArea::new();
+        let model = Rc::new(RefCell::new(model));
+
+        widget.add_events(gdk::EventMask::BUTTON_PRESS_MASK.bits() as i32);
+        widget.add_events(gdk::EventMask::BUTTON_RELEASE_MASK.bits() as i32);
+        widget.add_events(gdk::EventMask::SCROLL_MASK.bits() as i32);
+
+        connect!(relm, widget, connect_button_release_event(_, e), return (Some(MusicMsg::Click(e.get_button(), e.get_state())), Inhibit(true)));
+        connect!(relm, widget, connect_scroll_event(_, e), return (Some(MusicMsg::Scroll(e.get_direction())), Inhibit(true)));
+
+        // The queue around the current song and the saved playlists, opened with ctrl+click
+        let songs     = gtk::ListBox::new();
+        let playlists = gtk::ListBox::new();
+
+        let lyrics = gtk::Label::new(None);
+        lyrics.set_justify(gtk::Justification::Center);
+        lyrics.set_no_show_all(true);
+
+        let content = gtk::Box::new(gtk::Orientation::Vertical, model.borrow().config.dpi_scale(6));
+        content.add(&lyrics);
+        content.add(&gtk::Label::new(Some("Queue")));
+        content.add(&songs);
+        content.add(&gtk::Separator::new(gtk::Orientation::Horizontal));
+        content.add(&gtk::Label::new(Some("Playlists")));
+        content.add(&playlists);
+
+        let toggles = [
+            (PlayOption::Random,  "random"),
+            (PlayOption::Repeat,  "repeat"),
+            (PlayOption::Single,  "single"),
+            (PlayOption::Consume, "consume"),
+        ].iter().map(|&(option, label)| {
+            let button = gtk::ToggleButton::new_with_label(label);
+            connect!(relm, button, connect_toggled(button), MusicMsg::SetOption(option, button.get_active()));
+            (option, button)
+        }).collect::<Vec<_>>();
+
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, model.borrow().config.dpi_scale(4));
+        for &(_, ref button) in &toggles {
+            buttons.add(button);
         }
 
-        let font = pango::FontDescription::from_string("Droid Sans Mono 9");
-        layout.set_text(text, text.len() as i32);
-        layout.set_font_description(Some(&font));
+        let outputs = gtk::ListBox::new();
+        connect!(relm, outputs, connect_row_activated(_, row), MusicMsg::ToggleOutput(row.get_index()));
 
-        let available_space = width - used_width - margin;
-        let extents = layout.get_extents().1;
+        let options = gtk::Box::new(gtk::Orientation::Vertical, model.borrow().config.dpi_scale(6));
+        options.add(&gtk::Separator::new(gtk::Orientation::Horizontal));
+        options.add(&buttons);
+        options.add(&gtk::Label::new(Some("Outputs")));
+        options.add(&outputs);
+        options.set_no_show_all(true);
+        content.add(&options);
 
-        let (text_x, text_y) = (extents.x as f64 / pango::SCALE as f64,
-                                extents.y as f64 / pango::SCALE as f64);
-        let (text_width, text_height) = (extents.width  as f64 / pango::SCALE as f64,
-                                         extents.height as f64 / pango::SCALE as f64);
+        let popover = gtk::Popover::new(Some(&widget));
+        popover.set_position(gtk::PositionType::Top);
+        popover.add(&content);
 
-        let x = used_width + available_space / 2.0 - text_width  / 2.0 - text_x;
-        let y =                       height / 2.0 - text_height / 2.0 - text_y;
+        connect!(relm, songs, connect_row_activated(_, row), MusicMsg::PlaySong(row.get_index()));
+        connect!(relm, playlists, connect_row_activated(_, row), MusicMsg::LoadPlaylist(row.get_index()));
 
-        let Color(r, g, b, a) = self.color;
-        context.set_source_rgba(r, g, b, a);
+        widget.connect_draw(clone!(model => move |widget, cx| {
+            MusicWidget::render(&model.borrow(), widget, cx);
+            MusicWidget::scroll(&model, widget);
+            Inhibit(false)
+        }));
 
-        context.move_to(x, y);
-        pangocairo::show_layout(&context, &layout);
+        MusicWidget {
+            model,
+            widget,
+            popover,
+            lyrics,
+            songs,
+            playlists,
+            options,
+            toggles,
+            outputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn marquee() {
+        let offsets = [ 0.0, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.5, 5.0, 5.5, 6.0, 6.5 ]
+            .iter()
+            .map(|&t| marquee_offset(20.0, 20.0, t))
+            .collect::<Vec<_>>();
 
-        used_width += text_width + margin;
+        // waits 1.5s, scrolls for 1s, waits 1.5s, scrolls back for 1s and starts over
+        assert_eq!(offsets, vec![ 0.0, 0.0, 0.0, 10.0, 20.0, 20.0, 20.0, 10.0, 0.0, 0.0, 0.0, 0.0 ]);
+    }
+
+    #[test]
+    fn art_cache() {
+        let mut cache = ArtCache::new();
+        for n in 0 .. ART_CACHED {
+            cache.insert(n.to_string(), None);
+        }
 
-        widget.set_size_request(used_width as i32, -1);
+        // The first album was used last, so the second one makes room
+        assert!(cache.touch("0"));
+        cache.insert("new".to_string(), None);
 
-        Inhibit(false)
+        assert!(cache.touch("0"));
+        assert!(!cache.touch("1"));
+        assert!(cache.touch("new"));
+        assert_eq!(cache.entries.len(), ART_CACHED);
     }
 }
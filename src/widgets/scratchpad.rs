@@ -0,0 +1,255 @@
+extern crate cairo;
+extern crate gdk;
+extern crate gtk;
+extern crate pango;
+extern crate pangocairo;
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+
+use gtk::prelude::*;
+use self::pango::prelude::LayoutExt;
+use relm::{Channel, Relm, Update, Widget};
+
+use ::color::Color;
+use ::config::Config;
+use ::ipc::{self, Connection, Event, Node};
+
+pub struct ScratchpadModel {
+    config:     &'static Config,
+    channel:    Channel<ScratchpadMsg>,
+    scratchpad: Scratchpad,
+}
+
+/// Shows how many windows are hidden in the scratchpad, and takes up no space while it's empty.
+pub struct ScratchpadWidget {
+    model:  Rc<RefCell<ScratchpadModel>>,
+    widget: gtk::DrawingArea,
+}
+
+#[derive(Debug, Msg)]
+pub enum ScratchpadMsg {
+    Change(Scratchpad),
+    Click,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scratchpad {
+    windows: usize,
+    /// Whether any of the windows is urgent, which no workspace shows.
+    urgent:  bool,
+}
+
+impl<'a> From<&'a Node> for Scratchpad {
+    /// Finds the scratchpad workspace in the tree. i3 and sway both keep it on a hidden output.
+    fn from(tree: &Node) -> Self {
+        fn find(node: &Node) -> Option<&Node> {
+            if node.kind == "workspace" && node.name.as_ref().map_or(false, |name| name == "__i3_scratch") {
+                return Some(node);
+            }
+
+            node.nodes.iter().filter_map(find).next()
+        }
+
+        let windows = find(tree).map(Node::windows).unwrap_or_default();
+
+        Scratchpad {
+            windows: windows.len(),
+            urgent:  windows.iter().any(|window| window.urgent),
+        }
+    }
+}
+
+impl ScratchpadWidget {
+    fn render(model: &ScratchpadModel, widget: &gtk::DrawingArea, cx: &cairo::Context) {
+        let height = widget.get_allocated_height() as f64;
+        let dpi    = model.config.dpi;
+
+        if model.scratchpad.windows == 0 { return }
+
+        let font = pango::FontDescription::from_string(&format!("Droid Sans Mono {}", model.config.dpi_scale(8)));
+        let layout = pangocairo::functions::create_layout(cx).unwrap();
+        layout.set_text(&model.scratchpad.windows.to_string());
+        layout.set_font_description(Some(&font));
+
+        // Sized like a workspace, so it reads as part of the strip
+        let item_height = height * 0.6;
+        let item_width  = 22.0 * dpi;
+        let margin      = model.config.dpi_scale(6) as f64;
+        let line_width  = (1.0 * dpi).floor() * 2.0;
+        let top         = (height / 2.0 - item_height / 2.0).ceil();
+
+        cx.rectangle(margin, top, item_width, item_height.floor());
+        if model.scratchpad.urgent {
+            cx.set_source_rgba(1.0, 0.7, 0.0, 0.92);
+            cx.fill_preserve();
+        } else {
+            cx.set_source_rgba(0.4, 0.4, 0.4, 0.92);
+        }
+        cx.set_line_width(line_width);
+        cx.stroke();
+
+        let Color(r, g, b, a) = if model.scratchpad.urgent {
+            Color(0.1, 0.1, 0.1, 0.92)
+        } else {
+            model.config.get_color("white")
+        };
+        cx.set_source_rgba(r, g, b, a);
+
+        let (text_width, text_height) = layout.get_pixel_size();
+        cx.move_to(margin + item_width / 2.0 - text_width as f64 / 2.0, height / 2.0 - text_height as f64 / 2.0);
+        pangocairo::functions::show_layout(cx, &layout);
+
+        widget.set_size_request((item_width + margin * 2.0) as i32, -1);
+    }
+}
+
+/// Emits the state of the scratchpad whenever windows come, go, move or become urgent, until the
+/// connection to the window manager is lost.
+fn watch<F>(path: &Path, mut emit: F) -> io::Result<()>
+where
+    F: FnMut(Scratchpad),
+{
+    let mut i3       = Connection::connect_to(path)?;
+    let mut listener = Connection::connect_to(path)?.subscribe(&[ "window" ])?;
+
+    loop {
+        emit(Scratchpad::from(&i3.get_tree()?));
+
+        loop {
+            match listener.next_event()? {
+                Event::Window(ref e) if [ "new", "close", "move", "urgent" ].contains(&e.change.as_str()) => break,
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl Update for ScratchpadWidget {
+    type Model = ScratchpadModel;
+    type ModelParam = &'static Config;
+    type Msg = ScratchpadMsg;
+
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        let stream = relm.stream().clone();
+
+        let (channel, sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
+
+        thread::spawn(move || {
+            ipc::supervise(
+                |path| watch(path, |scratchpad| {
+                    sx.send(ScratchpadMsg::Change(scratchpad));
+                }),
+                || {
+                    sx.send(ScratchpadMsg::Change(Scratchpad::default()));
+                },
+            );
+        });
+
+        ScratchpadModel {
+            config,
+            channel,
+            scratchpad: Scratchpad::default(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        use self::ScratchpadMsg::*;
+        match msg {
+            Change(scratchpad) => {
+                self.widget.set_visible(scratchpad.windows > 0);
+                self.model.borrow_mut().scratchpad = scratchpad;
+            },
+            Click => {
+                let res = Connection::connect().and_then(|mut i3| i3.run_command("scratchpad show"));
+                if let Err(e) = res {
+                    println!("failed to show the scratchpad: {}", e);
+                }
+            },
+        }
+        self.widget.queue_draw();
+    }
+}
+
+impl Widget for ScratchpadWidget {
+    type Root = gtk::DrawingArea;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let widget = gtk::DrawingArea::new();
+        let model = Rc::new(RefCell::new(model));
+
+        // Hidden until a window is moved to the scratchpad, even when the bar calls show_all
+        widget.set_no_show_all(true);
+
+        widget.add_events(gdk::EventMask::BUTTON_PRESS_MASK.bits() as i32);
+        widget.add_events(gdk::EventMask::BUTTON_RELEASE_MASK.bits() as i32);
+
+        connect!(relm, widget, connect_button_release_event(_, e), return if e.get_button() == 1 {
+            (Some(ScratchpadMsg::Click), Inhibit(true))
+        }
+        else {
+            (None, Inhibit(false))
+        });
+
+        widget.connect_draw(clone!(model => move |widget, cx| {
+            ScratchpadWidget::render(&model.borrow(), widget, cx);
+            Inhibit(false)
+        }));
+
+        ScratchpadWidget {
+            model,
+            widget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::ipc::{self, fake::FakeServer};
+    use super::*;
+
+    const TREE: &str = r#"{"type":"root","nodes":[
+        {"type":"output","name":"__i3","nodes":[
+            {"type":"con","name":"content","nodes":[
+                {"type":"workspace","name":"__i3_scratch","nodes":[],"floating_nodes":[
+                    {"type":"floating_con","nodes":[{"type":"con","window":1,"urgent":true}]},
+                    {"type":"floating_con","nodes":[{"type":"con","app_id":"pavucontrol"}]}
+                ]}
+            ]}
+        ]},
+        {"type":"output","name":"DP-1","nodes":[
+            {"type":"con","name":"content","nodes":[
+                {"type":"workspace","name":"1-1","nodes":[{"type":"con","window":2}]}
+            ]}
+        ]}
+    ]}"#;
+
+    #[test]
+    fn counts_scratchpad_windows() {
+        let server = FakeServer::start(vec![ (ipc::GET_TREE, TREE) ], vec![
+            (ipc::EVENT_WINDOW, r#"{"change":"focus","container":{}}"#),
+            (ipc::EVENT_WINDOW, r#"{"change":"move","container":{}}"#),
+        ]);
+
+        let mut updates = vec![];
+        let _ = watch(&server.path, |scratchpad| updates.push(scratchpad));
+
+        // once on connect, once for the move but not for the focus change
+        assert_eq!(updates, vec![ Scratchpad { windows: 2, urgent: true }; 2 ]);
+    }
+
+    #[test]
+    fn no_scratchpad() {
+        let tree = ::serde_json::from_str::<Node>(r#"{"type":"root","nodes":[]}"#).unwrap();
+        assert_eq!(Scratchpad::from(&tree), Scratchpad::default());
+    }
+}
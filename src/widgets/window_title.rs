@@ -0,0 +1,266 @@
+extern crate cairo;
+extern crate gtk;
+extern crate pango;
+extern crate pangocairo;
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+
+use gtk::prelude::*;
+use self::pango::prelude::LayoutExt;
+use relm::{Channel, Relm, Update, Widget};
+
+use ::color::Color;
+use ::config::{Config, WindowTitleConfig};
+use ::ipc::{self, Connection, Event, Node};
+
+pub struct WindowTitleModel {
+    config:  &'static Config,
+    channel: Channel<WindowTitleMsg>,
+    window:  Option<FocusedWindow>,
+}
+
+pub struct WindowTitleWidget {
+    model:  Rc<RefCell<WindowTitleModel>>,
+    widget: gtk::DrawingArea,
+}
+
+#[derive(Debug, Msg)]
+pub enum WindowTitleMsg {
+    /// The focused window changed, or there is none because the workspace is empty.
+    Focus(Option<FocusedWindow>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FocusedWindow {
+    title:    String,
+    class:    Option<String>,
+    instance: Option<String>,
+}
+
+impl<'a> From<&'a Node> for FocusedWindow {
+    fn from(node: &Node) -> Self {
+        FocusedWindow {
+            title:    node.name.clone().unwrap_or_default(),
+            class:    node.class().map(String::from),
+            instance: node.instance().map(String::from),
+        }
+    }
+}
+
+impl WindowTitleWidget {
+    fn text(config: &WindowTitleConfig, window: &FocusedWindow) -> String {
+        let mut title = window.title.clone();
+        for rewrite in &config.rewrites {
+            let applies = match (&rewrite.class, &window.class) {
+                (None,        _)           => true,
+                (Some(class), Some(other)) => class.eq_ignore_ascii_case(other),
+                (Some(_),     None)        => false,
+            };
+
+            if applies {
+                title = title.replace(&rewrite.from, &rewrite.to);
+            }
+        }
+
+        let mut prefix = vec![];
+        if config.show_class    { prefix.extend(window.class.as_ref()); }
+        if config.show_instance { prefix.extend(window.instance.as_ref()); }
+
+        if prefix.is_empty() {
+            title
+        } else {
+            format!("[{}] {}", prefix.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(":"), title)
+        }
+    }
+
+    fn render(model: &WindowTitleModel, widget: &gtk::DrawingArea, cx: &cairo::Context) {
+        let height = widget.get_allocated_height() as f64;
+
+        let window = match model.window {
+            Some(ref window) => window,
+            None             => {
+                widget.set_size_request(0, -1);
+                return;
+            },
+        };
+
+        let font = pango::FontDescription::from_string(&format!("Droid Sans Mono {}", model.config.dpi_scale(10)));
+        let layout = pangocairo::functions::create_layout(cx).unwrap();
+        layout.set_text(&Self::text(&model.config.window_title, window));
+        layout.set_font_description(Some(&font));
+        layout.set_width(model.config.dpi_scale(model.config.window_title.max_width) * pango::SCALE);
+        layout.set_ellipsize(pango::EllipsizeMode::End);
+
+        let margin = model.config.dpi_scale(12) as f64;
+        let (text_width, text_height) = layout.get_pixel_size();
+
+        let Color(r, g, b, a) = model.config.get_color("white");
+        cx.set_source_rgba(r, g, b, a);
+
+        cx.move_to(margin, height / 2.0 - text_height as f64 / 2.0);
+        pangocairo::functions::show_layout(cx, &layout);
+
+        widget.set_size_request(text_width + 2 * margin as i32, -1);
+    }
+}
+
+/// Emits the focused window every time it or its title changes, until the connection to the
+/// window manager is lost.
+fn watch<F>(path: &Path, mut emit: F) -> io::Result<()>
+where
+    F: FnMut(Option<FocusedWindow>),
+{
+    let mut listener = Connection::connect_to(path)?.subscribe(&[ "window", "workspace" ])?;
+
+    let tree = Connection::connect_to(path)?.get_tree()?;
+    emit(tree.find_focused().filter(|n| n.is_window()).map(FocusedWindow::from));
+
+    loop {
+        match listener.next_event()? {
+            Event::Window(e) => match e.change.as_str() {
+                "focus"                        => emit(Some(FocusedWindow::from(&e.container))),
+                "title" if e.container.focused => emit(Some(FocusedWindow::from(&e.container))),
+                "close" if e.container.focused => emit(None),
+                _                              => (),
+            },
+
+            // Switching to a workspace with windows focuses one of them, which sends its own
+            // event. An empty one doesn't, so the title has to be cleared here.
+            Event::Workspace(e) => {
+                if e.change == "focus" && e.current.map_or(true, |ws| ws.windows().is_empty()) {
+                    emit(None);
+                }
+            },
+
+            _ => (),
+        }
+    }
+}
+
+impl Update for WindowTitleWidget {
+    type Model = WindowTitleModel;
+    type ModelParam = &'static Config;
+    type Msg = WindowTitleMsg;
+
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        let stream = relm.stream().clone();
+
+        let (channel, sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
+
+        thread::spawn(move || {
+            ipc::supervise(
+                |path| watch(path, |window| {
+                    sx.send(WindowTitleMsg::Focus(window));
+                }),
+                || {
+                    sx.send(WindowTitleMsg::Focus(None));
+                },
+            );
+        });
+
+        WindowTitleModel {
+            config,
+            channel,
+            window: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        use self::WindowTitleMsg::*;
+        match msg {
+            Focus(w) => self.model.borrow_mut().window = w,
+        }
+        self.widget.queue_draw();
+    }
+}
+
+impl Widget for WindowTitleWidget {
+    type Root = gtk::DrawingArea;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(_relm: &Relm<Self>, model: Self::Model) -> Self {
+        let widget = gtk::DrawingArea::new();
+        let model = Rc::new(RefCell::new(model));
+
+        widget.connect_draw(clone!(model => move |widget, cx| {
+            WindowTitleWidget::render(&model.borrow(), widget, cx);
+            Inhibit(false)
+        }));
+
+        WindowTitleWidget {
+            model,
+            widget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::config::TitleRewrite;
+    use ::ipc::{self, fake::FakeServer};
+    use super::*;
+
+    #[test]
+    fn follows_focus() {
+        let server = FakeServer::start(vec![
+            (ipc::GET_TREE, r#"{"type":"root","nodes":[{"type":"workspace","focused":true}]}"#),
+        ], vec![
+            (ipc::EVENT_WINDOW, r#"{"change":"focus","container":{"focused":true,"name":"vim",
+                "window":1,"window_properties":{"class":"URxvt","instance":"urxvt"}}}"#),
+            (ipc::EVENT_WINDOW, r#"{"change":"title","container":{"focused":true,"name":"zsh",
+                "window":1,"window_properties":{"class":"URxvt","instance":"urxvt"}}}"#),
+            (ipc::EVENT_WINDOW, r#"{"change":"title","container":{"focused":false,"name":"mpv",
+                "app_id":"mpv"}}"#),
+            (ipc::EVENT_WORKSPACE, r#"{"change":"focus","current":{"type":"workspace","nodes":[]}}"#),
+        ]);
+
+        let mut titles = vec![];
+        let _ = watch(&server.path, |w| titles.push(w.map(|w| w.title)));
+
+        assert_eq!(titles, vec![ None, Some("vim".to_string()), Some("zsh".to_string()), None ]);
+    }
+
+    fn rewrite(class: Option<&str>, from: &str, to: &str) -> TitleRewrite {
+        TitleRewrite { class: class.map(String::from), from: from.to_string(), to: to.to_string() }
+    }
+
+    fn window(title: &str, class: Option<&str>, instance: Option<&str>) -> FocusedWindow {
+        FocusedWindow { title: title.to_string(), class: class.map(String::from), instance: instance.map(String::from) }
+    }
+
+    #[test]
+    fn rewrites_titles() {
+        let mut config = WindowTitleConfig {
+            max_width:     400.0,
+            show_class:    false,
+            show_instance: false,
+            rewrites:      vec![
+                rewrite(None,            " - Mozilla Firefox", ""),
+                rewrite(Some("firefox"), "GitHub",             "GH"),
+                rewrite(Some("URxvt"),   "~",                  "home"),
+            ],
+        };
+
+        // Rules without a class apply to all windows, ones with a class regardless of its case
+        assert_eq!(WindowTitleWidget::text(&config, &window("GitHub - Mozilla Firefox", Some("Firefox"), None)), "GH");
+        assert_eq!(WindowTitleWidget::text(&config, &window("GitHub - Mozilla Firefox", None, None)), "GitHub");
+        assert_eq!(WindowTitleWidget::text(&config, &window("vim ~", Some("Emacs"), None)), "vim ~");
+
+        config.show_class = true;
+        assert_eq!(WindowTitleWidget::text(&config, &window("vim ~", Some("URxvt"), Some("urxvt"))), "[URxvt] vim home");
+        assert_eq!(WindowTitleWidget::text(&config, &window("vim ~", None, Some("urxvt"))), "vim ~");
+
+        config.show_instance = true;
+        assert_eq!(WindowTitleWidget::text(&config, &window("vim ~", Some("URxvt"), Some("urxvt"))), "[URxvt:urxvt] vim home");
+        assert_eq!(WindowTitleWidget::text(&config, &window("mpv", None, Some("mpv"))), "[mpv] mpv");
+    }
+}
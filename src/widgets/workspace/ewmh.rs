@@ -0,0 +1,492 @@
+extern crate x11;
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_int, c_long, c_uchar, c_ulong};
+use std::ptr;
+use std::slice;
+use std::sync::{Once, ONCE_INIT};
+use std::thread;
+use std::time::Duration;
+
+use relm_core::Sender;
+use self::x11::xlib;
+
+use ::ipc;
+use super::{Backend, Item, State, WindowInfo, WorkspaceMsg};
+
+/// Workspaces from any window manager that follows the EWMH spec, read from properties on the
+/// root window and its clients.
+pub struct Ewmh;
+
+/// Makes Xlib safe to use from the watcher thread as well as GDK's. Has to be called before any
+/// other Xlib call, so before GTK starts.
+pub fn init_threads() {
+    unsafe { xlib::XInitThreads(); }
+}
+
+impl Backend for Ewmh {
+    fn spawn(&self, sx: Sender<WorkspaceMsg>) {
+        install_error_handler();
+
+        thread::spawn(move || {
+            let mut backoff = ipc::MIN_BACKOFF;
+
+            loop {
+                match Display::open(None) {
+                    Ok(display) => watch(&display, |items| {
+                        sx.send(WorkspaceMsg::Items(items));
+                    }),
+                    Err(e) => {
+                        println!("can't watch the desktops: {}, retrying in {}ms", e, backoff);
+                        sx.send(WorkspaceMsg::Disconnected);
+
+                        thread::sleep(Duration::from_millis(backoff));
+                        backoff = (backoff * 2).min(ipc::MAX_BACKOFF);
+                    },
+                }
+            }
+        });
+    }
+
+    fn switch_to(&self, item: &Item) -> Result<(), String> {
+        install_error_handler();
+        Display::open(None)?.switch_to(item.workspace.1 - 1);
+        Ok(())
+    }
+}
+
+/// Desktop index EWMH uses for windows that are on all desktops.
+const ALL_DESKTOPS: c_ulong = 0xFFFFFFFF;
+
+struct Atoms {
+    number_of_desktops: xlib::Atom,
+    current_desktop:    xlib::Atom,
+    desktop_names:      xlib::Atom,
+    client_list:        xlib::Atom,
+    wm_desktop:         xlib::Atom,
+    wm_state:           xlib::Atom,
+    demands_attention:  xlib::Atom,
+    wm_name:            xlib::Atom,
+    utf8_string:        xlib::Atom,
+}
+
+impl Atoms {
+    /// Properties that change what's shown; anything else is ignored.
+    fn relevant(&self, atom: xlib::Atom) -> bool {
+        [
+            self.number_of_desktops, self.current_desktop, self.desktop_names, self.client_list,
+            self.wm_desktop, self.wm_state, self.wm_name,
+            xlib::XA_WM_HINTS, xlib::XA_WM_NAME, xlib::XA_WM_CLASS,
+        ].contains(&atom)
+    }
+}
+
+/// Each connection is only ever used from the thread that opened it, but Xlib's global state is
+/// shared with GDK, hence `init_threads`.
+struct Display {
+    ptr:   *mut xlib::Display,
+    root:  xlib::Window,
+    atoms: Atoms,
+}
+
+type ErrorHandler = unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int;
+
+static INSTALL_ERROR_HANDLER: Once = ONCE_INIT;
+static mut PREVIOUS_ERROR_HANDLER: Option<ErrorHandler> = None;
+
+/// Windows can be destroyed between reading the client list and reading their properties. The
+/// default handler exits on the resulting errors, so ignore those and pass the rest on to
+/// whichever handler was installed before (GDK's).
+unsafe extern "C" fn ignore_bad_window(display: *mut xlib::Display, event: *mut xlib::XErrorEvent) -> c_int {
+    if (*event).error_code == xlib::BadWindow {
+        return 0;
+    }
+
+    match PREVIOUS_ERROR_HANDLER {
+        Some(handler) => handler(display, event),
+        None          => 0,
+    }
+}
+
+/// Installs `ignore_bad_window` once, on the main thread so it doesn't race with GDK. That's after
+/// GTK started, since GDK installs its own handler then, which is the one to pass errors on to.
+fn install_error_handler() {
+    INSTALL_ERROR_HANDLER.call_once(|| unsafe {
+        PREVIOUS_ERROR_HANDLER = xlib::XSetErrorHandler(Some(ignore_bad_window));
+    });
+}
+
+impl Display {
+    /// Opens the display named by `$DISPLAY`, or the given one.
+    fn open(name: Option<&str>) -> Result<Display, String> {
+        let name = name.map(|name| CString::new(name).unwrap());
+
+        unsafe {
+            let ptr = xlib::XOpenDisplay(name.as_ref().map_or(ptr::null(), |name| name.as_ptr()));
+            if ptr.is_null() {
+                return Err("couldn't open the X display".to_string());
+            }
+
+            let intern = |name: &str| {
+                let name = CString::new(name).unwrap();
+                xlib::XInternAtom(ptr, name.as_ptr(), xlib::False)
+            };
+
+            let atoms = Atoms {
+                number_of_desktops: intern("_NET_NUMBER_OF_DESKTOPS"),
+                current_desktop:    intern("_NET_CURRENT_DESKTOP"),
+                desktop_names:      intern("_NET_DESKTOP_NAMES"),
+                client_list:        intern("_NET_CLIENT_LIST"),
+                wm_desktop:         intern("_NET_WM_DESKTOP"),
+                wm_state:           intern("_NET_WM_STATE"),
+                demands_attention:  intern("_NET_WM_STATE_DEMANDS_ATTENTION"),
+                wm_name:            intern("_NET_WM_NAME"),
+                utf8_string:        intern("UTF8_STRING"),
+            };
+
+            Ok(Display {
+                ptr,
+                root: xlib::XDefaultRootWindow(ptr),
+                atoms,
+            })
+        }
+    }
+
+    /// Reads a property of the given format, which is 8 for strings and 32 for everything else.
+    /// Xlib returns 32-bit values as longs, so `T` should be `c_ulong` for those.
+    fn get_property<T: Copy>(&self, window: xlib::Window, property: xlib::Atom, kind: xlib::Atom, format: c_int) -> Option<Vec<T>> {
+        unsafe {
+            let mut actual_kind   = 0;
+            let mut actual_format = 0;
+            let mut count         = 0;
+            let mut bytes_after   = 0;
+            let mut data: *mut c_uchar = ptr::null_mut();
+
+            let status = xlib::XGetWindowProperty(
+                self.ptr, window, property, 0, c_long::from(i32::max_value()) / 4, xlib::False, kind,
+                &mut actual_kind, &mut actual_format, &mut count, &mut bytes_after, &mut data,
+            );
+
+            if status != c_int::from(xlib::Success) || data.is_null() {
+                return None;
+            }
+
+            let values = if actual_kind == kind && actual_format == format {
+                Some(slice::from_raw_parts(data as *const T, count as usize).to_vec())
+            } else {
+                None
+            };
+
+            xlib::XFree(data as *mut _);
+            values
+        }
+    }
+
+    fn get_cardinal(&self, window: xlib::Window, property: xlib::Atom) -> Option<c_ulong> {
+        self.get_property(window, property, xlib::XA_CARDINAL, 32)
+            .and_then(|values| values.first().cloned())
+    }
+
+    fn get_string(&self, window: xlib::Window, property: xlib::Atom, kind: xlib::Atom) -> Option<String> {
+        self.get_property::<u8>(window, property, kind, 8)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn is_urgent(&self, window: xlib::Window) -> bool {
+        let hinted = unsafe {
+            let hints = xlib::XGetWMHints(self.ptr, window);
+            if hints.is_null() {
+                false
+            } else {
+                let urgent = (*hints).flags & xlib::XUrgencyHint != 0;
+                xlib::XFree(hints as *mut _);
+                urgent
+            }
+        };
+
+        hinted || self.get_property::<c_ulong>(window, self.atoms.wm_state, xlib::XA_ATOM, 32)
+            .map_or(false, |state| state.contains(&self.atoms.demands_attention))
+    }
+
+    fn class(&self, window: xlib::Window) -> Option<String> {
+        unsafe {
+            let mut hint: xlib::XClassHint = mem::zeroed();
+            if xlib::XGetClassHint(self.ptr, window, &mut hint) == 0 {
+                return None;
+            }
+
+            let class = if hint.res_class.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(hint.res_class).to_string_lossy().into_owned())
+            };
+
+            if !hint.res_name.is_null()  { xlib::XFree(hint.res_name as *mut _); }
+            if !hint.res_class.is_null() { xlib::XFree(hint.res_class as *mut _); }
+
+            class
+        }
+    }
+
+    fn title(&self, window: xlib::Window) -> String {
+        self.get_string(window, self.atoms.wm_name, self.atoms.utf8_string)
+            .or_else(|| self.get_string(window, xlib::XA_WM_NAME, xlib::XA_STRING))
+            .unwrap_or_default()
+    }
+
+    /// Reads the desktops and the windows on them. Also returns the client windows, so their
+    /// property changes can be listened to.
+    fn desktops(&self) -> (Vec<Item>, Vec<xlib::Window>) {
+        let count   = self.get_cardinal(self.root, self.atoms.number_of_desktops).unwrap_or(0) as usize;
+        let current = self.get_cardinal(self.root, self.atoms.current_desktop);
+
+        let names = self.get_property::<u8>(self.root, self.atoms.desktop_names, self.atoms.utf8_string, 8)
+            .unwrap_or_default();
+        let names = names.split(|b| *b == 0)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect::<Vec<_>>();
+
+        let clients = self.get_property::<c_ulong>(self.root, self.atoms.client_list, xlib::XA_WINDOW, 32)
+            .unwrap_or_default();
+
+        let mut windows = vec![ vec![]; count ];
+        let mut urgent  = vec![ false; count ];
+        for &client in &clients {
+            let desktop = match self.get_cardinal(client, self.atoms.wm_desktop) {
+                Some(ALL_DESKTOPS) | None         => continue,
+                Some(desktop) if desktop as usize >= count => continue,
+                Some(desktop)                     => desktop as usize,
+            };
+
+            urgent[desktop] |= self.is_urgent(client);
+            windows[desktop].push(WindowInfo {
+                title:  self.title(client),
+                class:  self.class(client),
+                layout: String::new(),
+                marks:  vec![],
+            });
+        }
+
+        let items = windows.into_iter().enumerate().map(|(i, windows)| {
+            let state = if urgent[i] { State::Urgent }
+            else if current == Some(i as c_ulong) { State::Active }
+            else if !windows.is_empty() { State::Inhibited }
+            else { State::Phantom };
+
+            Item {
+                workspace: (1, i as i64 + 1),
+                name:      names.get(i).filter(|name| !name.is_empty()).cloned().unwrap_or_else(|| (i + 1).to_string()),
+                label:     None,
+                output:    String::new(),
+                position:  0.0 .. 0.0,
+                state,
+                windows,
+            }
+        }).collect();
+
+        (items, clients)
+    }
+
+    /// Asks the window manager to switch to a desktop, by its index.
+    fn switch_to(&self, desktop: i64) {
+        unsafe {
+            let mut data = xlib::ClientMessageData::new();
+            data.set_long(0, desktop as c_long);
+            data.set_long(1, xlib::CurrentTime as c_long);
+
+            let mut event: xlib::XEvent = mem::zeroed();
+            event.client_message = xlib::XClientMessageEvent {
+                type_:        xlib::ClientMessage,
+                serial:       0,
+                send_event:   xlib::True,
+                display:      self.ptr,
+                window:       self.root,
+                message_type: self.atoms.current_desktop,
+                format:       32,
+                data,
+            };
+
+            let mask = xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask;
+            xlib::XSendEvent(self.ptr, self.root, xlib::False, mask, &mut event);
+            xlib::XFlush(self.ptr);
+        }
+    }
+}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        unsafe { xlib::XCloseDisplay(self.ptr); }
+    }
+}
+
+/// Emits a fresh set of items whenever a relevant property changes on the root window or one of
+/// the clients. Never returns, an X connection error exits the process anyway.
+fn watch<F>(display: &Display, mut emit: F) -> !
+where
+    F: FnMut(Vec<Item>),
+{
+    unsafe { xlib::XSelectInput(display.ptr, display.root, xlib::PropertyChangeMask); }
+
+    loop {
+        let (items, clients) = display.desktops();
+
+        // Selecting again for known clients is harmless, and new ones show up in the client list
+        for client in clients {
+            unsafe { xlib::XSelectInput(display.ptr, client, xlib::PropertyChangeMask); }
+        }
+
+        emit(items);
+
+        unsafe {
+            let mut event: xlib::XEvent = mem::zeroed();
+            loop {
+                xlib::XNextEvent(display.ptr, &mut event);
+                if event.get_type() == xlib::PropertyNotify && display.atoms.relevant(event.property.atom) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader};
+    use std::os::raw::c_char;
+    use std::process::{Child, Command, Stdio};
+
+    use super::*;
+
+    /// A virtual X server to play window manager on, killed when dropped.
+    struct Xvfb {
+        child: Child,
+        name:  String,
+    }
+
+    impl Xvfb {
+        /// Starts Xvfb on a display number it picks itself, so it can't end up talking to a
+        /// server that's already running. Xvfb writes the number once it accepts connections.
+        fn start() -> Xvfb {
+            let child = Command::new("Xvfb")
+                .args(&[ "-displayfd", "1", "-nolisten", "tcp" ])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("failed to start Xvfb");
+
+            let mut xvfb = Xvfb { child, name: String::new() };
+
+            let mut number = String::new();
+            BufReader::new(xvfb.child.stdout.take().unwrap()).read_line(&mut number).unwrap();
+            assert!(!number.trim().is_empty(), "Xvfb exited without opening a display");
+
+            xvfb.name = format!(":{}", number.trim());
+            xvfb
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    impl Display {
+        fn set_property<T: Copy>(&self, window: xlib::Window, property: xlib::Atom, kind: xlib::Atom, format: c_int, values: &[T]) {
+            unsafe {
+                xlib::XChangeProperty(
+                    self.ptr, window, property, kind, format, xlib::PropModeReplace,
+                    values.as_ptr() as *const c_uchar, values.len() as c_int,
+                );
+                xlib::XSync(self.ptr, xlib::False);
+            }
+        }
+
+        fn create_client(&self, desktop: c_ulong, class: &str, title: &str, urgent: bool) -> xlib::Window {
+            unsafe {
+                let window = xlib::XCreateSimpleWindow(self.ptr, self.root, 0, 0, 10, 10, 0, 0, 0);
+
+                let class = CString::new(class).unwrap();
+                let mut hint = xlib::XClassHint {
+                    res_name:  class.as_ptr() as *mut c_char,
+                    res_class: class.as_ptr() as *mut c_char,
+                };
+                xlib::XSetClassHint(self.ptr, window, &mut hint);
+
+                if urgent {
+                    let mut hints: xlib::XWMHints = mem::zeroed();
+                    hints.flags = xlib::XUrgencyHint;
+                    xlib::XSetWMHints(self.ptr, window, &mut hints);
+                }
+
+                self.set_property(window, self.atoms.wm_name, self.atoms.utf8_string, 8, title.as_bytes());
+                self.set_property(window, self.atoms.wm_desktop, xlib::XA_CARDINAL, 32, &[ desktop ]);
+
+                window
+            }
+        }
+    }
+
+    // Needs Xvfb, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn reads_desktops() {
+        let xvfb = Xvfb::start();
+        let name = &xvfb.name;
+
+        let wm = Display::open(Some(name)).unwrap();
+        let (root, atoms) = (wm.root, &wm.atoms);
+
+        wm.set_property(root, atoms.number_of_desktops, xlib::XA_CARDINAL, 32, &[ 3 as c_ulong ]);
+        wm.set_property(root, atoms.current_desktop,    xlib::XA_CARDINAL, 32, &[ 0 as c_ulong ]);
+        wm.set_property(root, atoms.desktop_names,      atoms.utf8_string, 8, b"web\0code\0\0");
+
+        let clients = [
+            wm.create_client(0,            "Firefox", "Mozilla Firefox", false),
+            wm.create_client(2,            "URxvt",   "vim",             true),
+            wm.create_client(ALL_DESKTOPS, "Conky",   "conky",           false),
+        ];
+        wm.set_property(root, atoms.client_list, xlib::XA_WINDOW, 32, &clients);
+
+        let display = Display::open(Some(name)).unwrap();
+        let (items, _) = display.desktops();
+
+        let summary = items.iter()
+            .map(|item| (item.name.as_str(), &item.state, item.windows.iter().map(|w| w.title.as_str()).collect()))
+            .collect::<Vec<(_, _, Vec<_>)>>();
+
+        assert_eq!(summary, vec![
+            ("web",  &State::Active,  vec![ "Mozilla Firefox" ]),
+            ("code", &State::Phantom, vec![]),
+            ("3",    &State::Urgent,  vec![ "vim" ]),
+        ]);
+    }
+
+    // Needs Xvfb, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn switches_desktop() {
+        let xvfb = Xvfb::start();
+        let name = &xvfb.name;
+
+        // Redirecting the root's substructure is what makes a window manager receive requests
+        let wm = Display::open(Some(name)).unwrap();
+        unsafe {
+            xlib::XSelectInput(wm.ptr, wm.root, xlib::SubstructureRedirectMask);
+            xlib::XSync(wm.ptr, xlib::False);
+        }
+
+        Display::open(Some(name)).unwrap().switch_to(2);
+
+        let event = unsafe {
+            let mut event: xlib::XEvent = mem::zeroed();
+            xlib::XNextEvent(wm.ptr, &mut event);
+            event.client_message
+        };
+
+        assert_eq!(event.type_, xlib::ClientMessage);
+        assert_eq!(event.message_type, wm.atoms.current_desktop);
+        assert_eq!(event.data.get_long(0), 2);
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::thread;
+
+use relm_core::Sender;
+
+use ::ipc::{self, Connection, Event, Node};
+use super::{Backend, Item, State, WindowInfo, WorkspaceMsg};
+
+/// Workspaces from i3 or sway, over their IPC socket.
+pub struct I3;
+
+impl Backend for I3 {
+    fn spawn(&self, sx: Sender<WorkspaceMsg>) {
+        thread::spawn(move || {
+            ipc::supervise(
+                |path| watch(path, |items| {
+                    sx.send(WorkspaceMsg::Items(items));
+                }),
+                || {
+                    sx.send(WorkspaceMsg::Disconnected);
+                },
+            );
+        });
+    }
+
+    fn switch_to(&self, item: &Item) -> Result<(), String> {
+        let mut command = format!("workspace {}", quote(&item.name));
+
+        // New workspaces are created on the focused output, so focus the right one first
+        let exists = item.state != State::Phantom && item.state != State::Add;
+        if !exists && !item.output.is_empty() {
+            command = format!("focus output {}; {}", quote(&item.output), command);
+        }
+
+        run_command(&command)
+    }
+
+    fn editable(&self) -> bool {
+        true
+    }
+
+    fn rename(&self, item: &Item, label: &str) -> Result<(), String> {
+        let mut name = format!("{}-{}", item.workspace.0, item.workspace.1);
+        if !label.is_empty() {
+            name = format!("{}:{}", name, label);
+        }
+
+        run_command(&format!("rename workspace {} to {}", quote(&item.name), quote(&name)))
+    }
+}
+
+/// Quotes a command argument, escaping backslashes first so they can't escape the quotes.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs a command on a connection of its own, failing if i3 rejects it.
+fn run_command(command: &str) -> Result<(), String> {
+    let outcomes = Connection::connect()
+        .and_then(|mut i3| i3.run_command(command))
+        .map_err(|e| e.to_string())?;
+
+    match outcomes.into_iter().find(|outcome| !outcome.success) {
+        Some(outcome) => Err(outcome.error.unwrap_or_else(|| format!("{} failed", command))),
+        None          => Ok(()),
+    }
+}
+
+/// Turns workspace names such as 1-2 (screen-workspace) or 1-2:web into a tuple of the numbers
+/// and the label
+fn parse_workspace_name(name: &str) -> Result<((i64, i64), Option<&str>), &'static str> {
+    let (numbers, label) = match name.find(':') {
+        Some(i) => (&name[..i], Some(&name[i + 1..]).filter(|label| !label.is_empty())),
+        None    => (name, None),
+    };
+
+    if numbers.len() < 3                        { return Err("name too short"); }
+    if numbers.len() != numbers.chars().count() { return Err("name contains multibyte characters"); }
+
+    let (screen, workspace) = numbers.split_at(1);
+    let screen    = screen.parse().map_err(|_| "invalid workspace name")?;
+    let workspace = (&workspace[1..]).parse().map_err(|_| "invalid workspace name")?;
+
+    Ok(((screen, workspace), label))
+}
+
+/// Collects the windows on each workspace in the tree, by workspace name.
+fn workspace_windows(tree: &Node) -> HashMap<String, Vec<WindowInfo>> {
+    fn collect_windows(node: &Node, layout: &str, windows: &mut Vec<WindowInfo>) {
+        if node.is_window() {
+            windows.push(WindowInfo {
+                title:  node.name.clone().unwrap_or_default(),
+                class:  node.class().map(String::from),
+                layout: layout.to_string(),
+                marks:  node.marks.clone(),
+            });
+            return;
+        }
+
+        let layout = if node.kind == "floating_con" { "floating" } else { &node.layout };
+        for child in &node.nodes {
+            collect_windows(child, layout, windows);
+        }
+        for child in &node.floating_nodes {
+            collect_windows(child, "floating", windows);
+        }
+    }
+
+    fn collect(node: &Node, map: &mut HashMap<String, Vec<WindowInfo>>) {
+        if node.kind == "workspace" {
+            let mut windows = vec![];
+            collect_windows(node, &node.layout, &mut windows);
+
+            map.insert(node.name.clone().unwrap_or_default(), windows);
+            return;
+        }
+
+        for child in node.nodes.iter().chain(&node.floating_nodes) {
+            collect(child, map);
+        }
+    }
+
+    let mut map = HashMap::new();
+    collect(tree, &mut map);
+    map
+}
+
+/// Turns the workspaces reported by the window manager into items. Workspaces with names that
+/// don't follow the screen-workspace scheme, like ones created by hand, are left out, and logged
+/// the first time they're seen.
+fn build_items(workspaces: &[ipc::Workspace], tree: &Node, skipped: &mut HashSet<String>) -> Vec<Item> {
+    let mut windows = workspace_windows(tree);
+
+    workspaces.iter().filter_map(|workspace| {
+        let (position, label) = match parse_workspace_name(&workspace.name) {
+            Ok(parsed) => parsed,
+            Err(e)     => {
+                if skipped.insert(workspace.name.clone()) {
+                    println!("not showing workspace {:?}: {}", workspace.name, e);
+                }
+                return None;
+            },
+        };
+
+        let state = if workspace.urgent { State::Urgent }
+        else if workspace.focused { State::Active }
+        else if workspace.visible { State::Visible }
+        else { State::Inhibited };
+
+        Some(Item {
+            workspace: position,
+            name:      workspace.name.clone(),
+            label:     label.map(String::from),
+            output:    workspace.output.clone(),
+            position:  0.0 .. 0.0,
+            state,
+            windows:   windows.remove(&workspace.name).unwrap_or_default(),
+        })
+    }).collect()
+}
+
+/// Emits a fresh set of items on every workspace event and every window event that changes what
+/// is shown about the windows on a workspace, until the connection to the window manager is lost.
+fn watch<F>(path: &Path, mut emit: F) -> io::Result<()>
+where
+    F: FnMut(Vec<Item>),
+{
+    let mut i3       = Connection::connect_to(path)?;
+    let mut listener = Connection::connect_to(path)?.subscribe(&[ "workspace", "window" ])?;
+    let mut skipped  = HashSet::new();
+
+    loop {
+        emit(build_items(&i3.get_workspaces()?, &i3.get_tree()?, &mut skipped));
+
+        loop {
+            match listener.next_event()? {
+                Event::Window(ref e) if ![ "new", "close", "move", "floating", "title", "mark" ].contains(&e.change.as_str()) => continue,
+                _ => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::ipc::fake::FakeServer;
+    use super::*;
+    use super::super::layout;
+    use super::super::test::config;
+
+    const I3_WORKSPACES: &str = r#"[
+        {"num":1,"name":"1-1","visible":true,"focused":true,"urgent":false,"output":"DP-1",
+         "rect":{"x":0,"y":0,"width":1920,"height":1080}},
+        {"num":1,"name":"1-3","visible":false,"focused":false,"urgent":true,"output":"DP-1",
+         "rect":{"x":0,"y":0,"width":1920,"height":1080}},
+        {"num":2,"name":"2-1","visible":true,"focused":false,"urgent":false,"output":"HDMI-1",
+         "rect":{"x":1920,"y":0,"width":1920,"height":1080}}
+    ]"#;
+
+    // sway adds node fields to workspaces and doesn't order them the same way
+    const SWAY_WORKSPACES: &str = r#"[
+        {"id":4,"type":"workspace","num":2,"name":"2-1","visible":true,"focused":false,
+         "urgent":false,"output":"HDMI-A-1","representation":"H[Alacritty]","layout":"splith",
+         "orientation":"horizontal","focus":[7],"nodes":[],"floating_nodes":[],"sticky":false,
+         "rect":{"x":1920,"y":0,"width":1920,"height":1080}},
+        {"id":5,"type":"workspace","num":1,"name":"1-1","visible":true,"focused":true,
+         "urgent":false,"output":"DP-1","representation":null,"layout":"splith",
+         "focus":[],"nodes":[],"floating_nodes":[],
+         "rect":{"x":0,"y":0,"width":1920,"height":1080}},
+        {"id":6,"type":"workspace","num":1,"name":"1-3","visible":false,"focused":false,
+         "urgent":true,"output":"DP-1","representation":"V[firefox]","layout":"splitv",
+         "focus":[9],"nodes":[],"floating_nodes":[],
+         "rect":{"x":0,"y":0,"width":1920,"height":1080}}
+    ]"#;
+
+    fn summarize(items: &[Item]) -> Vec<((i64, i64), &State)> {
+        items.iter().map(|item| (item.workspace, &item.state)).collect()
+    }
+
+    fn expected() -> Vec<((i64, i64), &'static State)> {
+        vec![
+            ((2, 1), &State::Visible),
+            ((2, 2), &State::Phantom),
+            ((2, 3), &State::Add),
+            ((1, 1), &State::Active),
+            ((1, 2), &State::Phantom),
+            ((1, 3), &State::Urgent),
+            ((1, 4), &State::Phantom),
+            ((1, 5), &State::Add),
+        ]
+    }
+
+    const TREE: &str = r#"{"type":"root","nodes":[
+        {"type":"output","name":"DP-1","nodes":[
+            {"type":"con","name":"content","nodes":[
+                {"type":"workspace","name":"1-1","nodes":[
+                    {"type":"con","layout":"splitv","nodes":[
+                        {"type":"con","window":1,"window_properties":{"class":"Firefox"}},
+                        {"type":"con","window":2,"window_properties":{"class":"URxvt"}}
+                    ]}
+                ],"floating_nodes":[
+                    {"type":"floating_con","nodes":[{"type":"con","app_id":"mpv"}]}
+                ]},
+                {"type":"workspace","name":"1-3","nodes":[
+                    {"type":"con","window":3,"window_properties":{"class":"URxvt"}}
+                ]}
+            ]}
+        ]}
+    ]}"#;
+
+    fn watch_fake(workspaces: &str) -> Vec<Vec<Item>> {
+        let server = FakeServer::start(vec![ (ipc::GET_WORKSPACES, workspaces), (ipc::GET_TREE, TREE) ], vec![
+            (ipc::EVENT_WINDOW,    r#"{"change":"focus","container":{}}"#),
+            (ipc::EVENT_WORKSPACE, r#"{"change":"focus"}"#),
+        ]);
+
+        let mut updates = vec![];
+        let res = watch(&server.path, |items| updates.push(layout(items, &config(), I3.editable())));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        updates
+    }
+
+    #[test]
+    fn i3_workspaces() {
+        let updates = watch_fake(I3_WORKSPACES);
+
+        // once on connect, once for the workspace event but not for the focus change
+        assert_eq!(updates.len(), 2);
+        assert_eq!(summarize(&updates[1]), expected());
+    }
+
+    #[test]
+    fn windows_per_workspace() {
+        let updates = watch_fake(I3_WORKSPACES);
+
+        let classes = updates[0].iter()
+            .map(|item| item.windows.iter().map(|w| w.class.clone().unwrap_or_default()).collect())
+            .collect::<Vec<Vec<_>>>();
+
+        assert_eq!(classes, vec![
+            vec![],
+            vec![],
+            vec![],
+            vec![ "Firefox".to_string(), "URxvt".to_string(), "mpv".to_string() ],
+            vec![],
+            vec![ "URxvt".to_string() ],
+            vec![],
+            vec![],
+        ]);
+
+        let layouts = updates[0][3].windows.iter().map(|w| w.layout.as_str()).collect::<Vec<_>>();
+        assert_eq!(layouts, vec![ "splitv", "splitv", "floating" ]);
+    }
+
+    #[test]
+    fn sway_workspaces() {
+        let updates = watch_fake(SWAY_WORKSPACES);
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(summarize(&updates[1]), expected());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(quote("1-1:web"), r#""1-1:web""#);
+        assert_eq!(quote(r#"1-2:say "hi""#), r#""1-2:say \"hi\"""#);
+        assert_eq!(quote(r"1-3:C:\"), r#""1-3:C:\\""#);
+    }
+
+    #[test]
+    fn labels() {
+        let server = FakeServer::start(vec![ (ipc::GET_WORKSPACES, r#"[
+            {"name":"1-1:web","output":"DP-1"},{"name":"1-2:","output":"DP-1"}
+        ]"#), (ipc::GET_TREE, TREE) ], vec![]);
+
+        let mut updates = vec![];
+        let _ = watch(&server.path, |items| updates.push(items));
+
+        let labels = updates[0].iter().map(|item| (item.workspace, item.label.clone())).collect::<Vec<_>>();
+        assert_eq!(labels, vec![ ((1, 1), Some("web".to_string())), ((1, 2), None) ]);
+    }
+
+    #[test]
+    fn unparseable_name() {
+        let server = FakeServer::start(vec![ (ipc::GET_WORKSPACES, r#"[
+            {"name":"1-1","output":"DP-1"},{"name":"web","output":"DP-1"},{"name":"1-2","output":"DP-1"}
+        ]"#), (ipc::GET_TREE, TREE) ], vec![]);
+
+        let mut updates = vec![];
+        let res = watch(&server.path, |items| updates.push(items));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let names = updates[0].iter().map(|item| item.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec![ "1-1", "1-2" ]);
+    }
+}
@@ -0,0 +1,633 @@
+extern crate cairo;
+extern crate gdk;
+extern crate gdk_sys;
+extern crate gtk;
+extern crate pango;
+extern crate pangocairo;
+extern crate time;
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+use self::pango::prelude::LayoutExt;
+use relm::{Channel, Relm, Update, Widget};
+use relm_core::Sender;
+
+use ::config::{Config, UrgentAnimation, WindowDisplay, WorkspaceBackend, WorkspacesConfig};
+use ::ipc;
+
+mod ewmh;
+mod i3;
+
+pub use self::ewmh::init_threads;
+
+/// Where the workspaces come from, and how to switch between them.
+pub trait Backend {
+    /// Starts watching the window manager on a thread of its own, which sends `Items` to the
+    /// widget whenever something changes.
+    fn spawn(&self, sx: Sender<WorkspaceMsg>);
+
+    /// Switches to a workspace, creating it if it's a phantom or an `Add` item.
+    fn switch_to(&self, item: &Item) -> Result<(), String>;
+
+    /// Whether workspaces can be created and renamed, otherwise there are no `Add` items.
+    fn editable(&self) -> bool {
+        false
+    }
+
+    /// Changes the label of a workspace, or removes it if the label is empty.
+    fn rename(&self, _item: &Item, _label: &str) -> Result<(), String> {
+        Err("renaming workspaces isn't supported".to_string())
+    }
+}
+
+pub struct WorkspaceModel {
+    config:    &'static Config,
+    channel:   Channel<WorkspaceMsg>,
+    backend:   Box<dyn Backend>,
+    items:     Vec<Item>,
+    connected: bool,
+    /// Whether the urgent animation timer is running.
+    animating: bool,
+    /// Whether blinking urgent workspaces are lit, toggled by the animation timer.
+    blink_on:  bool,
+    /// The workspace being renamed while the popover is open.
+    renaming:  Option<(i64, i64)>,
+}
+
+pub struct WorkspaceWidget {
+    model:   Rc<RefCell<WorkspaceModel>>,
+    widget:  gtk::DrawingArea,
+    popover: gtk::Popover,
+    entry:   gtk::Entry,
+}
+
+#[derive(Debug, Msg)]
+pub enum WorkspaceMsg {
+    Items(Vec<Item>),
+    /// The connection to the window manager was lost, the items are stale until it's back.
+    Disconnected,
+    Click((f64, f64)),
+    /// Opens the popover to rename the workspace at the position.
+    Edit((f64, f64)),
+    Rename(String),
+}
+
+#[derive(Debug)]
+pub struct Item {
+    /// Screen and workspace number. The EWMH backend puts all desktops on screen 1.
+    workspace: (i64, i64),
+    name: String,
+    /// The part of the name after the colon, like web in 1-2:web.
+    label: Option<String>,
+    /// Name of the output the workspace is on, empty for the EWMH backend.
+    output: String,
+    position: Range<f64>,
+    state: State,
+    windows: Vec<WindowInfo>,
+}
+
+#[derive(Clone, Debug)]
+struct WindowInfo {
+    title:  String,
+    class:  Option<String>,
+    /// Layout of the container holding the window, or `"floating"`. Empty when the window
+    /// manager doesn't tell.
+    layout: String,
+    marks:  Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// A workspace that doesn't actually exist (has no windows) but should be shown and can be
+    /// switched to.
+    Phantom,
+
+    /// A regular workspace that has windows in it, but isn't visible.
+    Inhibited,
+
+    /// A workspace that's visible but not currently active.
+    Visible,
+
+    /// The workspace that is currently active.
+    Active,
+
+    /// A workspace with an urgent window in it, even if the workspace is visible or active.
+    Urgent,
+
+    /// Not a workspace, but creates the next one on its screen when clicked.
+    Add,
+}
+
+impl WorkspaceWidget {
+    fn render(model: &WorkspaceModel, widget: &gtk::DrawingArea, cx: &cairo::Context) {
+        let width  = widget.get_allocated_width()  as f64;
+        let height = widget.get_allocated_height() as f64;
+        let dpi    = model.config.dpi;
+
+        if model.items.is_empty() { return }
+
+        // Leave room for the window labels inside the workspaces
+        let workspace_height = match model.config.workspaces.windows {
+            WindowDisplay::None => height * 0.25,
+            _                   => height * 0.6,
+        };
+        let skew_ratio = 0.2;
+        let skew = workspace_height * skew_ratio;
+
+        let required_width = model.items.last().unwrap().position.end * dpi + skew + 5.0;
+        if required_width > width {
+            widget.set_size_request(required_width as i32 + 5, height as i32);
+            return;
+        }
+        if required_width < width - 10.0 {
+            widget.set_size_request(required_width as i32 + 5, height as i32);
+            return;
+        }
+
+        let first_workspace = model.items.first().unwrap() as *const Item;
+        let last_workspace  = model.items.last().unwrap() as *const Item;
+
+        let line_width = (1.0 * dpi).floor() * 2.0;
+
+        let top    = (height / 2.0 - workspace_height / 2.0).ceil();
+        let bottom = (height / 2.0 + workspace_height / 2.0).floor();
+
+        // Keep showing the last known workspaces while disconnected, but faded out
+        if !model.connected {
+            cx.push_group();
+        }
+
+        cx.set_operator(cairo::Operator::Source);
+
+        for workspace in &model.items {
+            let mut left_top     = workspace.position.start * dpi;
+            let mut left_bottom  = left_top;
+            let mut right_top    = workspace.position.end * dpi;
+            let mut right_bottom = right_top;
+
+            if workspace as *const Item != first_workspace { left_top  += skew; left_bottom  -= skew; }
+            if workspace as *const Item != last_workspace  { right_top += skew; right_bottom -= skew; }
+
+            cx.move_to(left_top,     top);
+            cx.line_to(right_top,    top);
+            cx.line_to(right_bottom, bottom);
+            cx.line_to(left_bottom,  bottom);
+            cx.close_path();
+
+            match workspace.state {
+                State::Urgent    => cx.set_source_rgba(1.0, 0.7, 0.0, 0.92),
+                State::Active    => cx.set_source_rgba(1.0, 1.0, 1.0, 0.92),
+                State::Visible   => cx.set_source_rgba(0.7, 0.7, 0.7, 0.92),
+                State::Inhibited => cx.set_source_rgba(0.4, 0.4, 0.4, 0.92),
+                State::Phantom   => cx.set_source_rgba(0.4, 0.4, 0.4, 0.92),
+                State::Add       => cx.set_source_rgba(0.4, 0.4, 0.4, 0.92),
+            }
+
+            cx.set_line_width(line_width);
+            cx.stroke_preserve();
+
+            match workspace.state {
+                State::Urgent    => {
+                    // Fades between the inhibited and the urgent fill
+                    let glow = Self::urgent_glow(model);
+                    cx.set_source_rgba(0.4 + 0.6 * glow, 0.4 + 0.3 * glow, 0.4 - 0.4 * glow, 0.92)
+                },
+                State::Active    => cx.set_source_rgba(0.8, 0.8, 0.8, 0.92),
+                State::Visible   => cx.set_source_rgba(0.4, 0.4, 0.4, 0.92),
+                State::Inhibited => cx.set_source_rgba(0.4, 0.4, 0.4, 0.92),
+                State::Phantom   => cx.set_source_rgba(0.1, 0.1, 0.1, 0.92),
+                State::Add       => cx.set_source_rgba(0.1, 0.1, 0.1, 0.92),
+            }
+
+            cx.set_line_width(0.0);
+            cx.fill();
+
+            if let Some(label) = Self::item_label(model.config, workspace) {
+                let font = pango::FontDescription::from_string(&format!("Droid Sans Mono {}", model.config.dpi_scale(7)));
+                let layout = pangocairo::functions::create_layout(cx).unwrap();
+                layout.set_text(&label);
+                layout.set_font_description(Some(&font));
+                layout.set_width(((workspace.position.end - workspace.position.start) * dpi) as i32 * pango::SCALE);
+                layout.set_alignment(pango::Alignment::Center);
+                layout.set_ellipsize(pango::EllipsizeMode::End);
+
+                match workspace.state {
+                    State::Urgent | State::Active => cx.set_source_rgba(0.1, 0.1, 0.1, 0.92),
+                    _                             => cx.set_source_rgba(0.9, 0.9, 0.9, 0.92),
+                }
+
+                let (_, text_height) = layout.get_pixel_size();
+                cx.set_operator(cairo::Operator::Over);
+                cx.move_to(workspace.position.start * dpi, height / 2.0 - text_height as f64 / 2.0);
+                pangocairo::functions::show_layout(cx, &layout);
+                cx.set_operator(cairo::Operator::Source);
+            }
+        }
+
+        if !model.connected {
+            cx.pop_group_to_source();
+            cx.set_operator(cairo::Operator::Over);
+            cx.paint_with_alpha(0.3);
+        }
+    }
+
+    /// How much of the urgent color to fill urgent workspaces with right now, from 0 to 1.
+    fn urgent_glow(model: &WorkspaceModel) -> f64 {
+        let now = time::precise_time_s();
+
+        match model.config.workspaces.urgent_animation {
+            UrgentAnimation::None  => 1.0,
+            UrgentAnimation::Pulse => 0.5 + 0.5 * (now * PI * 2.0 / 1.5).cos(),
+            UrgentAnimation::Blink => if model.blink_on { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Redraws periodically while there's an urgent workspace, unless the animation is disabled
+    /// or already running: smoothly for pulsing, and only when it changes for blinking. The timer
+    /// stops itself once nothing is urgent anymore.
+    fn animate(&self) {
+        let interval = match self.model.borrow().config.workspaces.urgent_animation {
+            UrgentAnimation::None  => return,
+            UrgentAnimation::Pulse => 50,
+            UrgentAnimation::Blink => 500,
+        };
+
+        {
+            let mut model = self.model.borrow_mut();
+            if model.animating || !model.items.iter().any(|item| item.state == State::Urgent) {
+                return;
+            }
+            model.animating = true;
+            model.blink_on  = true;
+        }
+
+        let (model, widget) = (self.model.clone(), self.widget.clone());
+        gtk::timeout_add(interval, move || {
+            let mut model = model.borrow_mut();
+            model.animating = model.connected && model.items.iter().any(|item| item.state == State::Urgent);
+            model.blink_on  = !model.blink_on;
+
+            widget.queue_draw();
+            gtk::Continue(model.animating)
+        });
+    }
+
+    /// The window count or icons to show inside a workspace, depending on the config.
+    fn item_label(config: &Config, item: &Item) -> Option<String> {
+        if item.state == State::Add { return Some("+".to_string()) }
+        if item.windows.is_empty()  { return None }
+
+        match config.workspaces.windows {
+            WindowDisplay::None  => None,
+            WindowDisplay::Count => Some(item.windows.len().to_string()),
+            WindowDisplay::Icons => Some(item.windows.iter().map(|window| {
+                window.class.as_ref()
+                    .and_then(|class| config.workspaces.icons.get(&class.to_lowercase()))
+                    .unwrap_or(&config.workspaces.default_icon)
+                    .as_str()
+            }).collect()),
+        }
+    }
+
+    /// Lists the windows on the workspace under the pointer.
+    fn tooltip(model: &WorkspaceModel, x: f64) -> Option<String> {
+        let item = model.items.iter().find(|item| item.position.contains(&(x / model.config.dpi)))?;
+        if item.state == State::Add { return None }
+
+        let mut lines = vec![ item.name.clone() ];
+        if item.windows.is_empty() {
+            lines.push("no windows".to_string());
+        }
+
+        for window in &item.windows {
+            let mut line = format!("{} — {}", window.title, window.class.as_ref().map_or("?", |c| c.as_str()));
+            if !window.layout.is_empty() {
+                line.push_str(&format!(" ({})", window.layout));
+            }
+            if !window.marks.is_empty() {
+                line.push_str(&format!(" [{}]", window.marks.join(", ")));
+            }
+            lines.push(line);
+        }
+
+        Some(lines.join("\n"))
+    }
+
+    fn handle_click(&self, (x, _y): (f64, f64)) {
+        let model = self.model.borrow();
+        for item in &model.items {
+            if item.position.contains(&(x / model.config.dpi)) {
+                if let Err(e) = model.backend.switch_to(item) {
+                    println!("failed to switch workspace: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Opens the popover with the label of the workspace under the pointer, if it exists and the
+    /// backend can rename it.
+    fn handle_edit(&self, (x, _y): (f64, f64)) {
+        if !self.model.borrow().backend.editable() { return }
+
+        let dpi = self.model.borrow().config.dpi;
+
+        let (label, position) = {
+            let mut model = self.model.borrow_mut();
+            let (workspace, label, position) = match model.items.iter().find(|item| item.position.contains(&(x / dpi))) {
+                Some(item) if item.state != State::Phantom && item.state != State::Add => {
+                    (item.workspace, item.label.clone().unwrap_or_default(), item.position.clone())
+                },
+                _ => return,
+            };
+
+            model.renaming = Some(workspace);
+            (label, position)
+        };
+
+        self.entry.set_text(&label);
+        self.popover.set_pointing_to(&gdk::Rectangle {
+            x:      (position.start * dpi) as i32,
+            y:      0,
+            width:  ((position.end - position.start) * dpi) as i32,
+            height: self.widget.get_allocated_height(),
+        });
+        self.popover.show_all();
+        self.entry.grab_focus();
+    }
+
+    fn handle_rename(&self, label: &str) {
+        self.popover.hide();
+
+        let mut model = self.model.borrow_mut();
+        let workspace = match model.renaming.take() {
+            Some(workspace) => workspace,
+            None            => return,
+        };
+
+        // The workspace may have gone away while the popover was open
+        if let Some(item) = model.items.iter().find(|item| item.workspace == workspace) {
+            if let Err(e) = model.backend.rename(item, label.trim()) {
+                println!("failed to rename workspace: {}", e);
+            }
+        }
+    }
+}
+
+/// Positions the workspaces of all screens next to each other, filling in phantoms for missing
+/// ones. Phantoms are named following the i3 scheme of screen-workspace. If workspaces are
+/// `editable`, screens are also padded with phantoms up to their minimum number of workspaces, and
+/// get an item to create a new one. Other backends can't create desktops, so that would only show
+/// ones that can't be switched to.
+fn layout(mut workspaces: Vec<Item>, config: &WorkspacesConfig, editable: bool) -> Vec<Item> {
+    let screen_position = |screen: i64| {
+        config.screen_order.iter().position(|&s| s == screen).unwrap_or(config.screen_order.len())
+    };
+    workspaces.sort_by_key(|item| (screen_position(item.workspace.0), item.workspace));
+
+    let item_width = 35.0;
+    let add_width  = 20.0;
+    let padding    = 6.0;
+    let spacing    = 15.0;
+
+    let placeholder = |workspace: (i64, i64), output: &str, state: State| Item {
+        workspace,
+        name:     format!("{}-{}", workspace.0, workspace.1),
+        label:    None,
+        output:   output.to_string(),
+        position: 0.0 .. 0.0,
+        state,
+        windows:  vec![],
+    };
+
+    let mut items      = vec![];
+    let mut left       = -padding;
+    let mut workspaces = workspaces.into_iter().peekable();
+    while let Some((screen, output)) = workspaces.peek().map(|item| (item.workspace.0, item.output.clone())) {
+        let mut screen_items = vec![];
+        let mut last_desktop = 0;
+
+        while workspaces.peek().map_or(false, |item| item.workspace.0 == screen) {
+            let item = workspaces.next().unwrap();
+            for n in (last_desktop + 1) .. item.workspace.1 {
+                screen_items.push(placeholder((screen, n), &output, State::Phantom));
+            }
+
+            last_desktop = item.workspace.1;
+            screen_items.push(item);
+        }
+
+        if editable {
+            let min_desktops = config.min_desktops.get((screen - 1) as usize).cloned().unwrap_or(0);
+            for n in (last_desktop + 1) .. (min_desktops + 1) {
+                screen_items.push(placeholder((screen, n), &output, State::Phantom));
+                last_desktop = n;
+            }
+
+            screen_items.push(placeholder((screen, last_desktop + 1), &output, State::Add));
+        }
+
+        left += spacing;
+        for mut item in screen_items {
+            let width = if item.state == State::Add { add_width } else { item_width };
+
+            left += padding;
+            item.position = (left) .. (left + width);
+            left += width;
+
+            items.push(item);
+        }
+    }
+
+    items
+}
+
+/// Picks the backend for `Auto`: i3's IPC if i3 or sway is running, EWMH hints otherwise. Other
+/// backends are returned as they are.
+pub fn resolve_backend(backend: WorkspaceBackend) -> WorkspaceBackend {
+    match backend {
+        WorkspaceBackend::Auto => match ipc::socket_path() {
+            Some(_) => WorkspaceBackend::I3,
+            None    => WorkspaceBackend::Ewmh,
+        },
+        backend => backend,
+    }
+}
+
+impl Update for WorkspaceWidget {
+    type Model = WorkspaceModel;
+    type ModelParam = &'static Config;
+    type Msg = WorkspaceMsg;
+
+    // Return the initial model.
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        let stream = relm.stream().clone();
+
+        let (channel, sx) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
+
+        let backend: Box<dyn Backend> = match resolve_backend(config.workspaces.backend) {
+            WorkspaceBackend::I3 => Box::new(i3::I3),
+            _                    => Box::new(ewmh::Ewmh),
+        };
+
+        backend.spawn(sx);
+
+        WorkspaceModel {
+            config,
+            items: vec![],
+            channel,
+            backend,
+            connected: false,
+            animating: false,
+            blink_on:  true,
+            renaming:  None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        use self::WorkspaceMsg::*;
+        match msg {
+            Items(v) => {
+                {
+                    let mut model = self.model.borrow_mut();
+                    model.items     = layout(v, &model.config.workspaces, model.backend.editable());
+                    model.connected = true;
+                }
+
+                // Refresh the tooltip if it's showing
+                self.widget.trigger_tooltip_query();
+                self.animate();
+            },
+            Disconnected => self.model.borrow_mut().connected = false,
+            Click(e) => self.handle_click(e),
+            Edit(e) => self.handle_edit(e),
+            Rename(label) => self.handle_rename(&label),
+        }
+        self.widget.queue_draw();
+    }
+
+    fn subscriptions(&mut self, _relm: &Relm<Self>) {
+    }
+}
+
+impl Widget for WorkspaceWidget {
+    type Root = gtk::DrawingArea;
+
+    fn root(&self) -> Self::Root {
+        self.widget.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let widget = gtk::DrawingArea::new();
+        let model = Rc::new(RefCell::new(model));
+
+        widget.add_events(gdk::EventMask::BUTTON_PRESS_MASK.bits() as i32);
+        widget.add_events(gdk::EventMask::BUTTON_RELEASE_MASK.bits() as i32);
+
+        connect!(relm, widget, connect_button_release_event(_, e), return match e.get_button() {
+            1 => (Some(WorkspaceMsg::Click(e.get_position())), Inhibit(true)),
+            3 => (Some(WorkspaceMsg::Edit(e.get_position())), Inhibit(true)),
+            _ => (None, Inhibit(false)),
+        });
+
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text("label");
+
+        let popover = gtk::Popover::new(Some(&widget));
+        popover.set_position(gtk::PositionType::Top);
+        popover.add(&entry);
+
+        connect!(relm, entry, connect_activate(entry), WorkspaceMsg::Rename(entry.get_text().unwrap_or_default()));
+
+        widget.connect_draw(clone!(model => move |widget, cx| {
+            WorkspaceWidget::render(&model.borrow(), widget, cx);
+            Inhibit(false)
+        }));
+
+        widget.set_has_tooltip(true);
+        widget.connect_query_tooltip(clone!(model => move |_widget, x, _y, _keyboard, tooltip| {
+            match WorkspaceWidget::tooltip(&model.borrow(), x as f64) {
+                Some(text) => { tooltip.set_text(Some(text.as_str())); true },
+                None       => false,
+            }
+        }));
+
+        WorkspaceWidget {
+            model,
+            widget,
+            popover,
+            entry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use ::config::{UrgentAnimation, WindowDisplay, WorkspaceBackend, WorkspacesConfig};
+    use super::*;
+
+    pub fn config() -> WorkspacesConfig {
+        WorkspacesConfig {
+            backend:          WorkspaceBackend::Auto,
+            windows:          WindowDisplay::None,
+            icons:            HashMap::new(),
+            default_icon:     String::new(),
+            urgent_animation: UrgentAnimation::None,
+            min_desktops:     vec![ 4, 2, 1 ],
+            screen_order:     vec![ 2, 1, 3 ],
+        }
+    }
+
+    fn item(workspace: (i64, i64), output: &str) -> Item {
+        Item {
+            workspace,
+            name:     format!("{}-{}", workspace.0, workspace.1),
+            label:    None,
+            output:   output.to_string(),
+            position: 0.0 .. 0.0,
+            state:    State::Inhibited,
+            windows:  vec![],
+        }
+    }
+
+    #[test]
+    fn add_items() {
+        let items = layout(vec![ item((1, 6), "DP-1"), item((3, 1), "VGA-1"), item((1, 2), "DP-1") ], &config(), true);
+
+        let summary = items.iter()
+            .map(|item| (item.workspace, &item.state, item.output.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(summary, vec![
+            ((1, 1), &State::Phantom,   "DP-1"),
+            ((1, 2), &State::Inhibited, "DP-1"),
+            ((1, 3), &State::Phantom,   "DP-1"),
+            ((1, 4), &State::Phantom,   "DP-1"),
+            ((1, 5), &State::Phantom,   "DP-1"),
+            ((1, 6), &State::Inhibited, "DP-1"),
+            ((1, 7), &State::Add,       "DP-1"),
+            ((3, 1), &State::Inhibited, "VGA-1"),
+            ((3, 2), &State::Add,       "VGA-1"),
+        ]);
+
+        // No overlaps, and "+" items are narrower
+        assert!(items.windows(2).all(|pair| pair[0].position.end < pair[1].position.start));
+        assert!(items[6].position.end - items[6].position.start < items[5].position.end - items[5].position.start);
+    }
+
+    #[test]
+    fn fixed_desktops() {
+        // Like EWMH, which can't create desktops to fill up to the minimum
+        let items = layout(vec![ item((1, 1), ""), item((1, 2), "") ], &config(), false);
+
+        let summary = items.iter().map(|item| (item.workspace, &item.state)).collect::<Vec<_>>();
+        assert_eq!(summary, vec![ ((1, 1), &State::Inhibited), ((1, 2), &State::Inhibited) ]);
+    }
+}